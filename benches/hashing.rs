@@ -6,6 +6,17 @@ fn blake3(text: &String) -> String {
     return hex::encode(bytes_hash);
 }
 
+fn sha256(text: &String) -> String {
+    use sha2::{Digest, Sha256};
+    let bytes_hash = Sha256::digest(text.as_bytes()).to_vec();
+    return hex::encode(bytes_hash);
+}
+
+fn xxh3(text: &String) -> String {
+    let bytes_hash = xxhash_rust::xxh3::xxh3_128(text.as_bytes()).to_be_bytes();
+    return hex::encode(bytes_hash);
+}
+
 //////////////
 
 fn blake3_small_string_benchmark(c: &mut Criterion) {
@@ -24,12 +35,48 @@ fn blake3_large_string_benchmark(c: &mut Criterion) {
     });
 }
 
+fn sha256_small_string_benchmark(c: &mut Criterion) {
+    c.bench_function("sha256 small", |b| {
+        b.iter(|| sha256(black_box(&"Some text that is not very long...".to_string())))
+    });
+}
+
+fn sha256_large_string_benchmark(c: &mut Criterion) {
+    c.bench_function("sha256 large", |b| {
+        b.iter(|| {
+            sha256(black_box(
+                &std::iter::repeat("A").take(100000000).collect::<String>(),
+            ))
+        })
+    });
+}
+
+fn xxh3_small_string_benchmark(c: &mut Criterion) {
+    c.bench_function("xxh3 small", |b| {
+        b.iter(|| xxh3(black_box(&"Some text that is not very long...".to_string())))
+    });
+}
+
+fn xxh3_large_string_benchmark(c: &mut Criterion) {
+    c.bench_function("xxh3 large", |b| {
+        b.iter(|| {
+            xxh3(black_box(
+                &std::iter::repeat("A").take(100000000).collect::<String>(),
+            ))
+        })
+    });
+}
+
 criterion_group!(
     small_string_bencharmks,
     blake3_small_string_benchmark,
+    sha256_small_string_benchmark,
+    xxh3_small_string_benchmark,
 );
 criterion_group!(
     large_string_bencharmks,
-    blake3_large_string_benchmark
+    blake3_large_string_benchmark,
+    sha256_large_string_benchmark,
+    xxh3_large_string_benchmark
 );
 criterion_main!(small_string_bencharmks, large_string_bencharmks);
@@ -0,0 +1,56 @@
+use crate::tasks::Task;
+use anyhow::{Context, Result};
+
+// an entry in a batch file. each line is either a JSON object with the same fields as a task in
+// a task file, or the arguments for a single command, quoted like they would be in the shell.
+//
+// {"command": ["convert", "a.svg", "a.png"], "deps": ["a.svg"], "targets": ["a.png"]}
+// -d b.svg -t b.png -- convert b.svg b.png
+// b.svg == convert b.svg b.png == b.png
+pub enum Entry {
+    Record(Task),
+    Args(Vec<String>),
+}
+
+// parse a batch file. blank lines and lines starting with '#' are skipped.
+// returns the entries along with the (1 based) line they are on.
+pub fn parse(contents: &str) -> Result<Vec<(usize, Entry)>> {
+    let mut entries: Vec<(usize, Entry)> = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let entry = if line.starts_with('{') {
+            Entry::Record(
+                serde_json::from_str(line)
+                    .with_context(|| format!("Invalid JSON on line {}", i + 1))?,
+            )
+        } else {
+            Entry::Args(
+                shlex::split(line).with_context(|| format!("Invalid quoting on line {}", i + 1))?,
+            )
+        };
+        entries.push((i + 1, entry));
+    }
+    return Ok(entries);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_batch() {
+        let contents = "# images\n{\"command\": \"convert a.svg a.png\", \"deps\": [\"a.svg\"]}\n\n-d 'b c.svg' -- convert 'b c.svg' b.png\n";
+        let entries = parse(contents).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].0, 2);
+        assert!(matches!(&entries[0].1, Entry::Record(task) if task.deps.len() == 1));
+        assert_eq!(entries[1].0, 4);
+        assert!(
+            matches!(&entries[1].1, Entry::Args(args) if args[..2] == ["-d", "b c.svg"] && args.len() == 6)
+        );
+        assert!(parse("-d 'unterminated").is_err());
+    }
+}
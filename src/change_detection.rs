@@ -4,19 +4,309 @@ use dpc_pariter::IteratorExt as _;
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::os::unix::fs::MetadataExt;
 use std::path::PathBuf;
 use std::time::UNIX_EPOCH;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DependencyStatus {
     pub content_hash: String,
     pub mtime: u128,
+    #[serde(default)]
+    pub metadata_hash: Option<String>,
+    #[serde(default)]
+    pub ctime: u128,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub inode: u64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl DependencyStatus {
+    pub fn new(content_hash: String, stat: &FileStat) -> DependencyStatus {
+        return DependencyStatus {
+            content_hash,
+            mtime: stat.mtime,
+            metadata_hash: None,
+            ctime: stat.ctime,
+            size: stat.size,
+            inode: stat.inode,
+        };
+    }
+
+    // a dependency that is not a file (an environment variable for example)
+    // only has a hash.
+    pub fn from_hash(content_hash: String) -> DependencyStatus {
+        return DependencyStatus::new(
+            content_hash,
+            &FileStat {
+                mtime: 0,
+                ctime: 0,
+                size: 0,
+                inode: 0,
+            },
+        );
+    }
+
+    // check if the cached stat information matches a file's current stat information.
+    // if it does, the file has _probably_ not been modified.
+    pub fn stat_matches(&self, stat: &FileStat) -> bool {
+        return self.mtime == stat.mtime
+            && self.ctime == stat.ctime
+            && self.size == stat.size
+            && self.inode == stat.inode;
+    }
+
+    pub fn update_stat(&mut self, stat: &FileStat) {
+        self.mtime = stat.mtime;
+        self.ctime = stat.ctime;
+        self.size = stat.size;
+        self.inode = stat.inode;
+    }
+
+    // check if the cached hash was computed with the same algorithm and filters.
+    pub fn hashed_with(&self, options: &HashOptions) -> bool {
+        return prefixed(&self.content_hash).starts_with(&format!("{}:", options.key()));
+    }
+
+    // check if the cached hash is the same as a newly computed one.
+    pub fn hash_matches(&self, content_hash: &str) -> bool {
+        return prefixed(&self.content_hash) == content_hash;
+    }
+
+    // a file that was modified at (or after) the time the cache was written is "racily clean".
+    // it could have been modified again within the same timestamp tick without changing
+    // its mtime, so its contents have to be checked even if the stat information matches.
+    pub fn is_racy(&self, cache_mtime: Option<u128>) -> bool {
+        match cache_mtime {
+            Some(cache_mtime) => self.mtime >= cache_mtime,
+            None => false,
+        }
+    }
+}
+
+// the stat information we use to decide if a file _might_ have changed.
+// times are in microseconds from the UNIX epoch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileStat {
+    pub mtime: u128,
+    pub ctime: u128,
+    pub size: u64,
+    pub inode: u64,
+}
+
+// file attributes that can optionally be treated as part of a dependency.
+// none of these change a file's content (or mtime), so they are invisible
+// to the content hash unless asked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub enum MetadataField {
+    Mode,
+    Uid,
+    Gid,
+    Xattr,
+}
+
+// how files are compared to decide if they have changed.
+// the cheap modes only look at stat information and never read file contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum CompareMode {
+    Mtime,
+    #[value(name = "size+mtime")]
+    SizeMtime,
+    #[default]
+    Content,
+}
+
+// the hash functions that can be used for dependencies.
+// hashes are stored with the name of the algorithm as a prefix (i.e. `blake3:...`)
+// so that switching algorithms invalidates the cache instead of silently mismatching.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum HashAlgorithm {
+    #[default]
+    Blake3,
+    Sha256,
+    Xxh3,
+}
+
+impl HashAlgorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Blake3 => "blake3",
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Xxh3 => "xxh3",
+        }
+    }
+}
+
+// filters that are applied to the contents of a file before it is hashed
+// so that changes that don't mean anything don't count as changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum TextFilter {
+    /// Convert CRLF line endings to LF.
+    Crlf,
+    /// Strip whitespace from the end of each line.
+    TrailingWhitespace,
+}
+
+// options that control how a dependency is hashed.
+#[derive(Debug, Clone, Default)]
+pub struct HashOptions {
+    pub track_metadata: Vec<MetadataField>,
+    pub compare: CompareMode,
+    pub algorithm: HashAlgorithm,
+    pub filters: Vec<TextFilter>,
+    pub ignore_lines: Vec<regex::bytes::Regex>,
+}
+
+impl HashOptions {
+    // the prefix of the hash of a file's contents. the filters are part of it, so that
+    // changing them makes the cached hash useless, just like changing the algorithm does.
+    pub fn key(&self) -> String {
+        if self.filters.is_empty() && self.ignore_lines.is_empty() {
+            return self.algorithm.name().to_string();
+        }
+        let filters = format!(
+            "{:?}\n{}",
+            self.filters,
+            self.ignore_lines.iter().map(|r| r.as_str()).join("\n")
+        );
+        return format!(
+            "{}+{}",
+            self.algorithm.name(),
+            &hex::encode(hash_bytes(filters.as_bytes()))[..16]
+        );
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CommandStatus {
     pub exit_code: Option<i32>,
     pub dependencies: HashMap<String, DependencyStatus>,
+    // dependencies that were not given on the command line, but
+    // discovered when the command ran (i.e. listed in a depfile).
+    #[serde(default)]
+    pub discovered_dependencies: Vec<String>,
+    // targets that were discovered when the command ran (i.e. by tracing it).
+    #[serde(default)]
+    pub discovered_targets: Vec<String>,
+    // whether the command has been traced with --trace-deps, so the discovered
+    // dependencies and targets are known (even if there are none).
+    #[serde(default)]
+    pub traced: bool,
+    // the name given with --name, so that other commands can refer to this one.
+    #[serde(default)]
+    pub name: Option<String>,
+    // the number of times the command has been ran.
+    #[serde(default)]
+    pub run_count: u64,
+    // how the last run ended.
+    #[serde(default)]
+    pub outcome: Option<Outcome>,
+    // the most recent runs, oldest first.
+    #[serde(default)]
+    pub history: Vec<RunRecord>,
+    // the output of the last run, if it was captured with --capture-output.
+    #[serde(default)]
+    pub output: Option<CapturedOutput>,
+    // the log of the last run, if it was written with --log or --log-dir.
+    #[serde(default)]
+    pub log: Option<String>,
+    // the hash of the command given with --each, if this entry is for one of its files.
+    #[serde(default)]
+    pub each: Option<String>,
+}
+
+// output that is stored in the cache. each stream is compressed with gzip and base64 encoded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CapturedOutput {
+    pub stdout: String,
+    pub stderr: String,
+    // true if the command wrote more output than we keep.
+    pub truncated: bool,
+}
+
+impl CapturedOutput {
+    pub fn new(stdout: &[u8], stderr: &[u8], truncated: bool) -> Result<CapturedOutput> {
+        return Ok(CapturedOutput {
+            stdout: compress(stdout)?,
+            stderr: compress(stderr)?,
+            truncated,
+        });
+    }
+
+    pub fn stdout(&self) -> Result<Vec<u8>> {
+        return decompress(&self.stdout);
+    }
+
+    pub fn stderr(&self) -> Result<Vec<u8>> {
+        return decompress(&self.stderr);
+    }
+}
+
+fn compress(data: &[u8]) -> Result<String> {
+    use base64::Engine;
+    use std::io::Write;
+
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(data)?;
+    return Ok(base64::engine::general_purpose::STANDARD.encode(encoder.finish()?));
+}
+
+fn decompress(data: &str) -> Result<Vec<u8>> {
+    use base64::Engine;
+    use std::io::Read;
+
+    let compressed = base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .context("Captured output is not valid base64")?;
+    let mut decompressed: Vec<u8> = Vec::new();
+    flate2::read::GzDecoder::new(&compressed[..])
+        .read_to_end(&mut decompressed)
+        .context("Could not decompress captured output")?;
+    return Ok(decompressed);
+}
+
+// how many runs are kept in the history of a command.
+const HISTORY_LENGTH: usize = 10;
+
+// how an attempt to run a command ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Outcome {
+    Exited(i32),
+    // killed by a signal that we did not send.
+    Signaled,
+    TimedOut,
+    // interrupted by a signal sent to run-if (i.e. Ctrl-C).
+    Interrupted,
+}
+
+impl Outcome {
+    pub fn exit_code(&self) -> Option<i32> {
+        match self {
+            Outcome::Exited(code) => Some(*code),
+            _ => None,
+        }
+    }
+
+    pub fn success(&self) -> bool {
+        return *self == Outcome::Exited(0);
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attempt {
+    pub outcome: Outcome,
+    pub duration_ms: u64,
+}
+
+// a run of a command. a run can take several attempts when --retries is given.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunRecord {
+    // seconds from the UNIX epoch.
+    pub started: u64,
+    pub reasons: Vec<String>,
+    pub attempts: Vec<Attempt>,
 }
 
 impl CommandStatus {
@@ -24,8 +314,66 @@ impl CommandStatus {
         return CommandStatus {
             dependencies: HashMap::new(),
             exit_code: None,
+            discovered_dependencies: Vec::new(),
+            discovered_targets: Vec::new(),
+            traced: false,
+            name: None,
+            run_count: 0,
+            outcome: None,
+            history: Vec::new(),
+            output: None,
+            log: None,
+            each: None,
         };
     }
+
+    // check if the last run of the command failed. commands that have not ran have not failed.
+    pub fn failed(&self) -> bool {
+        match self.outcome {
+            Some(outcome) => return !outcome.success(),
+            None => return self.exit_code.is_some_and(|code| code != 0),
+        }
+    }
+
+    // check if the last run did not get to finish (it timed out, was interrupted or killed by a
+    // signal). the dependencies were recorded anyway, so such a run has to be repeated. a command
+    // that failed is not repeated, whether it was retried or not.
+    pub fn unfinished(&self) -> bool {
+        return matches!(
+            self.outcome,
+            Some(Outcome::TimedOut | Outcome::Interrupted | Outcome::Signaled)
+        );
+    }
+
+    pub fn record_run(&mut self, run: RunRecord) {
+        let outcome = run.attempts.last().map(|a| a.outcome);
+        self.outcome = outcome;
+        self.exit_code = outcome.and_then(|o| o.exit_code());
+        self.run_count += 1;
+        self.history.push(run);
+        if self.history.len() > HISTORY_LENGTH {
+            self.history.drain(..self.history.len() - HISTORY_LENGTH);
+        }
+    }
+
+    // update the hash of a dependency that is not a file.
+    // returns true if the dependency is new or its hash changed.
+    pub fn update_dependency(&mut self, key: &str, content_hash: String) -> bool {
+        match self.dependencies.get_mut(key) {
+            Some(status) => {
+                if status.hash_matches(&content_hash) {
+                    return false;
+                }
+                status.content_hash = content_hash;
+                return true;
+            }
+            None => {
+                self.dependencies
+                    .insert(key.to_string(), DependencyStatus::from_hash(content_hash));
+                return true;
+            }
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -39,6 +387,26 @@ impl StatusCache {
             commands: HashMap::new(),
         };
     }
+
+    // find the command that was given a name with --name.
+    pub fn find_named(&self, name: &str) -> Option<(&String, &CommandStatus)> {
+        return self
+            .commands
+            .iter()
+            .find(|(_, c)| c.name.as_deref() == Some(name));
+    }
+
+    // give a command a name. names are unique, so any other command
+    // with the same name loses it.
+    pub fn set_name(&mut self, cmd_hash: &str, name: &str) {
+        for (hash, status) in self.commands.iter_mut() {
+            if hash == cmd_hash {
+                status.name = Some(name.to_string());
+            } else if status.name.as_deref() == Some(name) {
+                status.name = None;
+            }
+        }
+    }
 }
 
 // we need a function to hash an array of bytes for using
@@ -46,8 +414,9 @@ impl StatusCache {
 // a function to hash a string that a user can use.
 //
 // we have tested several different hash functions and
-// are currently using blake3 because it is the fastest
-// in our tests/workspace/run-benchmarks.sh tests.
+// are using blake3 by default because it is the fastest
+// in our tests/workspace/run-benchmarks.sh tests. sha256
+// and xxh3 can be selected with the --hash option.
 //
 // we have commented out the hash functions that were
 // used in the past.
@@ -80,57 +449,209 @@ fn blake3_bytes(bytes: &[u8]) -> Vec<u8> {
     return hash.to_vec();
 }
 
+fn sha256_bytes(bytes: &[u8]) -> Vec<u8> {
+    // Slower than blake3, but useful for comparing against
+    // checksums that are published elsewhere.
+    use sha2::{Digest, Sha256};
+    return Sha256::digest(bytes).to_vec();
+}
+
+fn xxh3_bytes(bytes: &[u8]) -> Vec<u8> {
+    // Not a cryptographic hash, but very fast.
+    return xxhash_rust::xxh3::xxh3_128(bytes).to_be_bytes().to_vec();
+}
+
 ////////////////////////////////////////////
 
 fn hash_bytes(text: &[u8]) -> Vec<u8> {
     return blake3_bytes(text);
 }
 
+fn hash_bytes_with(algorithm: HashAlgorithm, bytes: &[u8]) -> String {
+    let hash = match algorithm {
+        HashAlgorithm::Blake3 => blake3_bytes(bytes),
+        HashAlgorithm::Sha256 => sha256_bytes(bytes),
+        HashAlgorithm::Xxh3 => xxh3_bytes(bytes),
+    };
+    return format!("{}:{}", algorithm.name(), hex::encode(hash));
+}
+
+// caches written before the hash algorithm could be chosen have blake3 hashes
+// without a prefix. they are still valid, so they should not cause a rerun.
+fn prefixed(content_hash: &str) -> String {
+    if content_hash.contains(':') {
+        return content_hash.to_string();
+    }
+    return format!("{}:{}", HashAlgorithm::Blake3.name(), content_hash);
+}
+
+// hash a string without an algorithm prefix. this is used
+// for keys in the cache, which should not change when the
+// dependency hash algorithm does.
 pub fn hash_string(text: &String) -> String {
     return hex::encode(hash_bytes(text.as_bytes()));
 }
 
-fn hash_file(file_path: &PathBuf) -> Result<String> {
+// hash a string with the algorithm selected in the options.
+pub fn hash_value(text: &str, options: &HashOptions) -> String {
+    return hash_bytes_with(options.algorithm, text.as_bytes());
+}
+
+fn hash_file(file_path: &PathBuf, options: &HashOptions) -> Result<String> {
+    match options.compare {
+        CompareMode::Mtime => {
+            return Ok(hash_value(
+                &format!("mtime={}", get_mtime(file_path)?),
+                options,
+            ));
+        }
+        CompareMode::SizeMtime => {
+            let stat = get_stat(file_path)?;
+            return Ok(hash_value(
+                &format!("size={},mtime={}", stat.size, stat.mtime),
+                options,
+            ));
+        }
+        CompareMode::Content => {}
+    }
     let data = std::fs::read(file_path)
         .with_context(|| format!("Could not read file '{}'", file_path.display()))?;
-    return Ok(hex::encode(&hash_bytes(&data)));
+    if options.filters.is_empty() && options.ignore_lines.is_empty() {
+        return Ok(hash_bytes_with(options.algorithm, &data));
+    }
+    let hash = hash_bytes_with(options.algorithm, &filter_contents(&data, options));
+    let (_, hash) = hash.split_once(':').unwrap();
+    return Ok(format!("{}:{}", options.key(), hash));
 }
-fn hash_dir(dir_path: &PathBuf) -> Result<String> {
+
+// apply the text filters in the options to the contents of a file.
+fn filter_contents(data: &[u8], options: &HashOptions) -> Vec<u8> {
+    let mut filtered: Vec<u8> = Vec::with_capacity(data.len());
+    for line in data.split_inclusive(|b| *b == b'\n') {
+        let (mut text, mut ending): (&[u8], &[u8]) = match line.strip_suffix(b"\n") {
+            Some(text) => (text, b"\n"),
+            None => (line, b""),
+        };
+        if let Some(stripped) = text.strip_suffix(b"\r") {
+            if options.filters.contains(&TextFilter::Crlf) {
+                text = stripped;
+            } else if !ending.is_empty() {
+                // keep the carriage return as part of the line ending
+                // so that trailing whitespace stripping does not remove it.
+                text = stripped;
+                ending = b"\r\n";
+            }
+        }
+        if options.filters.contains(&TextFilter::TrailingWhitespace) {
+            text = text.trim_ascii_end();
+        }
+        if options.ignore_lines.iter().any(|r| r.is_match(text)) {
+            continue;
+        }
+        filtered.extend_from_slice(text);
+        filtered.extend_from_slice(ending);
+    }
+    return filtered;
+}
+fn hash_dir(dir_path: &PathBuf, options: &HashOptions) -> Result<String> {
     // we can either get all of the files under the directory
     // at once and then hash each, or get only the files
     // in the top level and walk down into sub-directories.
     // getting them all up front will let us hash each in parallel.
     let fs = utils::FileSearch::new();
-    let files = fs.get_all_files_under(&dir_path)?;
+    let files = fs.get_all_files_under(dir_path)?;
+    let file_options = options.clone();
     let hashes = files
         .into_iter()
         .sorted()
-        .parallel_map(|p| -> Result<String> { Ok(hash_path(&p)? + "|" + p.to_str().unwrap()) });
+        .parallel_map(move |p| -> Result<String> { hash_file_entry(&p, &file_options) });
 
     // we want new directories to trigger a change, even if they are empty.
     // so we need to get the list of all directories that exist into the hash.
     // we'll just get a list of directories and tack it onto the end
     // of our hashes.
     let dirs = fs
-        .get_all_dirs_under(&dir_path)?
+        .get_all_dirs_under(dir_path)?
         .into_iter()
         .sorted()
-        .map(|p| -> Result<String> { Ok(p.to_string_lossy().into_owned()) });
+        .map(|p| -> Result<String> {
+            let mut entry = p.to_string_lossy().into_owned();
+            if let Some(metadata) = get_metadata_string(&p, &options.track_metadata)? {
+                entry = entry + "|" + &metadata;
+            }
+            Ok(entry)
+        });
 
     let joined_hash = hashes
         .chain(dirs)
         .reduce(|acc: Result<String>, e| Ok(acc? + "\n" + &e?))
         .unwrap_or(Ok(String::from("null")))?;
-    let hash = hash_string(&joined_hash);
+    let hash = hash_value(&joined_hash, options);
     return Ok(hash);
 }
 
-pub fn hash_path(path: &PathBuf) -> Result<String> {
+// the line that a file under a directory contributes to the directory's hash.
+// the directory's hash has the prefix already, so the lines leave it out.
+fn hash_file_entry(path: &PathBuf, options: &HashOptions) -> Result<String> {
+    let hash = hash_path(path, options)?;
+    let hash = hash.split_once(':').map_or(hash.as_str(), |(_, hash)| hash);
+    let mut entry = hash.to_string() + "|" + path.to_str().unwrap();
+    if let Some(metadata) = get_metadata_string(path, &options.track_metadata)? {
+        entry = entry + "|" + &metadata;
+    }
+    Ok(entry)
+}
+
+// hash the files under a directory that are tracked by git. untracked files are ignored,
+// and tracked files that have been deleted are included so that deleting them is a change.
+pub fn hash_git_tracked(dir_path: &PathBuf, options: &HashOptions) -> Result<String> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(dir_path)
+        .arg("ls-files")
+        .arg("-z")
+        .output()
+        .with_context(|| format!("Could not run git in '{}'", dir_path.display()))?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "Could not list files tracked by git in '{}': {}",
+            dir_path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    // files with merge conflicts are listed once for each stage.
+    let files = output
+        .stdout
+        .split(|b| *b == 0)
+        .filter(|p| !p.is_empty())
+        .map(|p| dir_path.join(std::ffi::OsStr::from_bytes(p)))
+        .sorted()
+        .dedup();
+    let file_options = options.clone();
+    let joined_hash = files
+        .parallel_map(move |p| -> Result<String> {
+            if p.is_file() {
+                return hash_file_entry(&p, &file_options);
+            }
+            if !p.exists() {
+                return Ok(String::from("deleted|") + p.to_str().unwrap());
+            }
+            // submodules are listed as directories. we don't look inside them.
+            Ok(p.to_string_lossy().into_owned())
+        })
+        .reduce(|acc: Result<String>, e| Ok(acc? + "\n" + &e?))
+        .unwrap_or(Ok(String::from("null")))?;
+    return Ok(hash_value(&joined_hash, options));
+}
+
+pub fn hash_path(path: &PathBuf, options: &HashOptions) -> Result<String> {
     if path.is_file() {
-        return hash_file(&path);
+        return hash_file(path, options);
     }
     if path.is_dir() {
-        return hash_dir(&path);
+        return hash_dir(path, options);
     }
 
     return Err(anyhow::anyhow!(
@@ -149,3 +670,98 @@ pub fn get_mtime(path: &PathBuf) -> Result<u128> {
         .unwrap()
         .as_micros())
 }
+
+// get a string describing the requested metadata fields of a file or directory.
+// returns None if no fields were requested.
+fn get_metadata_string(path: &PathBuf, fields: &[MetadataField]) -> Result<Option<String>> {
+    if fields.is_empty() {
+        return Ok(None);
+    }
+    let metadata = std::fs::metadata(path)
+        .with_context(|| format!("Could not get metadata of file '{}'", path.display()))?;
+    let mut parts: Vec<String> = Vec::new();
+    for field in fields.iter().sorted().dedup() {
+        match field {
+            MetadataField::Mode => parts.push(format!("mode={:o}", metadata.mode())),
+            MetadataField::Uid => parts.push(format!("uid={}", metadata.uid())),
+            MetadataField::Gid => parts.push(format!("gid={}", metadata.gid())),
+            MetadataField::Xattr => {
+                let names = xattr::list(path).with_context(|| {
+                    format!("Could not list extended attributes of '{}'", path.display())
+                })?;
+                for name in names.sorted() {
+                    let value = xattr::get(path, &name)
+                        .with_context(|| {
+                            format!(
+                                "Could not read extended attribute '{}' of '{}'",
+                                name.to_string_lossy(),
+                                path.display()
+                            )
+                        })?
+                        .unwrap_or_default();
+                    parts.push(format!(
+                        "xattr:{}={}",
+                        name.to_string_lossy(),
+                        hex::encode(value)
+                    ));
+                }
+            }
+        }
+    }
+    return Ok(Some(parts.join(",")));
+}
+
+// compute a hash of the requested metadata fields of a file or directory.
+// returns None if no fields were requested.
+pub fn hash_metadata(path: &PathBuf, options: &HashOptions) -> Result<Option<String>> {
+    return Ok(get_metadata_string(path, &options.track_metadata)?.map(|m| hash_value(&m, options)));
+}
+
+pub fn get_stat(path: &PathBuf) -> Result<FileStat> {
+    let metadata = std::fs::metadata(path)
+        .with_context(|| format!("Could not get metadata of file '{}'", path.display()))?;
+    Ok(FileStat {
+        mtime: metadata
+            .modified()
+            .with_context(|| format!("Could not get mtime of file '{}'", path.display()))?
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_micros(),
+        ctime: metadata.ctime() as u128 * 1_000_000 + metadata.ctime_nsec() as u128 / 1_000,
+        size: metadata.size(),
+        inode: metadata.ino(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_captured_output() {
+        let output = CapturedOutput::new(b"warning: unused variable\n", b"", false).unwrap();
+        assert_eq!(output.stdout().unwrap(), b"warning: unused variable\n");
+        assert_eq!(output.stderr().unwrap(), b"");
+    }
+
+    #[test]
+    fn test_filter_contents() {
+        let data = b"one  \r\ntwo\t\r\n# generated at 12:00\r\nthree";
+        let mut options = HashOptions {
+            filters: vec![TextFilter::Crlf],
+            ..Default::default()
+        };
+        assert_eq!(
+            filter_contents(data, &options),
+            b"one  \ntwo\t\n# generated at 12:00\nthree"
+        );
+        options.filters = vec![TextFilter::TrailingWhitespace];
+        assert_eq!(
+            filter_contents(data, &options),
+            b"one\r\ntwo\r\n# generated at 12:00\r\nthree"
+        );
+        options.filters = vec![TextFilter::Crlf, TextFilter::TrailingWhitespace];
+        options.ignore_lines = vec![regex::bytes::Regex::new("^# generated").unwrap()];
+        assert_eq!(filter_contents(data, &options), b"one\ntwo\nthree");
+    }
+}
@@ -0,0 +1,117 @@
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+
+// parse a Makefile style dependency file, like the ones written by `gcc -MD`,
+// `rustc --emit=dep-info`, or ninja, and return all of the prerequisites it lists.
+//
+// a depfile looks like
+//
+// build/main.o: src/main.c include/a.h \
+//   include/with\ space.h
+// include/a.h:
+//
+// the second rule (written by `gcc -MP`) has no prerequisites, so it does not contribute anything.
+pub fn parse(contents: &str) -> Vec<PathBuf> {
+    let mut prerequisites: Vec<PathBuf> = Vec::new();
+    // join continued lines first so that each rule is on a single line.
+    let joined = contents.replace("\\\r\n", " ").replace("\\\n", " ");
+    for line in joined.lines() {
+        if line.trim_start().starts_with('#') {
+            continue;
+        }
+        let Some(start) = find_rule_separator(line) else {
+            continue;
+        };
+        for word in split_words(&line[start..]) {
+            let path = PathBuf::from(word);
+            if !prerequisites.contains(&path) {
+                prerequisites.push(path);
+            }
+        }
+    }
+    return prerequisites;
+}
+
+pub fn parse_file(path: &PathBuf) -> Result<Vec<PathBuf>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read depfile '{}'", path.display()))?;
+    return Ok(parse(&contents));
+}
+
+// find the index just past the ':' that separates targets from prerequisites.
+// the separator must be followed by whitespace or the end of the line so that
+// paths containing ':' (i.e. C:\...) are not split.
+fn find_rule_separator(line: &str) -> Option<usize> {
+    let bytes = line.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b':' if i + 1 == bytes.len() || bytes[i + 1].is_ascii_whitespace() => {
+                return Some(i + 1)
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    return None;
+}
+
+// split a list of prerequisites on whitespace, handling escapes.
+fn split_words(text: &str) -> Vec<String> {
+    let mut words: Vec<String> = Vec::new();
+    let mut word = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if matches!(chars.peek(), Some(' ') | Some('#') | Some('\\')) => {
+                word.push(chars.next().unwrap());
+            }
+            '$' if chars.peek() == Some(&'$') => {
+                word.push(chars.next().unwrap());
+            }
+            c if c.is_whitespace() => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    return words;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_gcc_depfile() {
+        let contents = "build/main.o: src/main.c include/a.h \\\n  include/with\\ space.h \\\n include/cost$$.h\n\ninclude/a.h:\n";
+        assert_eq!(
+            parse(contents),
+            vec![
+                PathBuf::from("src/main.c"),
+                PathBuf::from("include/a.h"),
+                PathBuf::from("include/with space.h"),
+                PathBuf::from("include/cost$.h"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_multiple_rules() {
+        let contents = "# comment\nout/lib.rlib out/lib.d: src/lib.rs src/a.rs\n\nout/bin: src/main.rs src/a.rs\r\n";
+        assert_eq!(
+            parse(contents),
+            vec![
+                PathBuf::from("src/lib.rs"),
+                PathBuf::from("src/a.rs"),
+                PathBuf::from("src/main.rs"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use crate::change_detection::Outcome;
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+// the path of a new log in a log directory. logs are named after the command and the time
+// they were started, so every run gets its own log and they sort in the order they ran.
+pub fn path_in_dir(dir: &Path, label: &str, started: SystemTime) -> PathBuf {
+    let timestamp = humantime::format_rfc3339_millis(started)
+        .to_string()
+        .replace(':', "");
+    let label: String = label
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    return dir.join(format!("{}-{}.log", label, timestamp));
+}
+
+// create a log and write a header with the command and why it is running.
+pub fn create(
+    path: &Path,
+    command: &[String],
+    started: SystemTime,
+    reasons: &[&str],
+    changed: &[PathBuf],
+) -> Result<File> {
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Could not create log directory '{}'", dir.display()))?;
+    }
+    let mut file = File::create(path)
+        .with_context(|| format!("Could not create log file '{}'", path.display()))?;
+    let mut header = format!(
+        "# command: {}\n# started: {}\n# reasons: {}\n",
+        shlex::try_join(command.iter().map(|a| a.as_str())).unwrap_or_else(|_| command.join(" ")),
+        humantime::format_rfc3339_seconds(started),
+        reasons.join(", ")
+    );
+    for path in changed.iter() {
+        header.push_str(&format!("# changed: {}\n", path.display()));
+    }
+    file.write_all(header.as_bytes())
+        .with_context(|| format!("Could not write to log file '{}'", path.display()))?;
+    return Ok(file);
+}
+
+// add a line to a log, i.e. that the command is being retried.
+pub fn note(mut file: &File, message: &str) -> Result<()> {
+    writeln!(file, "# {}", message).context("Could not write to log file")?;
+    return Ok(());
+}
+
+// write a footer with the outcome of the last attempt and how long the command ran.
+pub fn finish(mut file: &File, outcome: Outcome, duration: Duration) -> Result<()> {
+    let outcome = match outcome {
+        Outcome::Exited(code) => format!("exit code: {}", code),
+        Outcome::Signaled => "outcome: killed by a signal".to_string(),
+        Outcome::TimedOut => "outcome: timed out".to_string(),
+        Outcome::Interrupted => "outcome: interrupted".to_string(),
+    };
+    // durations are rounded to milliseconds, the rest is noise.
+    let duration = Duration::from_millis(duration.as_millis() as u64);
+    file.write_all(
+        format!(
+            "# {}\n# duration: {}\n",
+            outcome,
+            humantime::format_duration(duration)
+        )
+        .as_bytes(),
+    )
+    .context("Could not write to log file")?;
+    return Ok(());
+}
@@ -1,16 +1,32 @@
+// explicit returns are used throughout.
+#![allow(clippy::needless_return)]
+
 use anyhow::{Context, Result};
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+use itertools::Itertools;
 use log::{debug, info, warn};
-use std::path::PathBuf;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
+use std::sync::Mutex;
 
+mod batch;
 mod change_detection;
+mod depfile;
+mod logfile;
+mod process;
+mod tasks;
+mod trace;
 mod utils;
+mod watch;
 
-use change_detection::{CommandStatus, DependencyStatus, StatusCache};
+use change_detection::{
+    Attempt, CapturedOutput, CommandStatus, CompareMode, DependencyStatus, HashAlgorithm,
+    HashOptions, MetadataField, Outcome, RunRecord, StatusCache, TextFilter,
+};
 
 #[derive(Parser)]
-#[command(version)]
+#[command(version, args_conflicts_with_subcommands = true)]
 /// Conditionally run a command, similar to make and checkexec.
 ///
 /// run-if runs a given command based on the status of dependencies, targets, and sentinels.
@@ -30,60 +46,403 @@ use change_detection::{CommandStatus, DependencyStatus, StatusCache};
 /// If a sentinel **does** exist, the command will be ran even if the dependencies have not changed.
 /// This can be used to cause a command to run only if some file that would be produced by another
 /// process is present. Can be useful for running commands that do some cleanup.
+///
+/// A command named `run`, `batch`, `watch`, or `logs` with no options in front of it is taken for
+/// the subcommand with the same name. Put `--` in front of it (i.e. `run-if -- watch ...`) to run
+/// the command instead.
 struct Cli {
+    #[command(subcommand)]
+    subcommand: Option<Subcommand>,
+    /// Specify the database file to use.
+    #[arg(long, global = true, default_value = ".run-if.json")]
+    database: PathBuf,
+    #[command(flatten)]
+    options: Options,
+
+    command: Vec<String>,
+}
+
+#[derive(clap::Subcommand)]
+enum Subcommand {
+    /// Run tasks declared in a task file, after the tasks they need. Runs every task if none are given.
+    Run {
+        /// The tasks to run.
+        tasks: Vec<String>,
+        /// The task file to read.
+        #[arg(short, long, default_value = "run-if.toml")]
+        file: PathBuf,
+        /// The number of tasks to run at the same time. Tasks still run after the tasks they need.
+        #[arg(short, long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+        jobs: u64,
+    },
+    /// Run the commands in a batch file ('-' for stdin), reading and writing the database only once.
+    /// Each line is either the arguments for a single command, like on the command line, or a JSON
+    /// object with the same fields as a task in a task file.
+    Batch {
+        /// The batch file to read.
+        file: PathBuf,
+        /// The number of commands to run at the same time.
+        #[arg(short, long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+        jobs: u64,
+    },
+    /// Watch the dependencies and targets and check if the command needs to run every time they change.
+    Watch {
+        #[command(flatten)]
+        options: Box<Options>,
+        /// Wait until nothing has changed for this many milliseconds before checking the command.
+        #[arg(long, value_name = "MS", default_value_t = 100)]
+        debounce: u64,
+        #[arg(required = true)]
+        command: Vec<String>,
+    },
+    /// Print the latest log of a command that was given a name with --name.
+    Logs {
+        /// The name of the command.
+        name: String,
+    },
+}
+
+// the options that decide if a single command should run.
+#[derive(clap::Args, Clone, Default)]
+struct Options {
     /// Specify a dependency. Must be a file or directory. Can be given multiple times.
     #[arg(short, long)]
     dependency: Vec<PathBuf>,
+    /// Specify a directory in a git repository as a dependency. Only the files under the directory that are
+    /// tracked by git are considered. Can be given multiple times.
+    #[arg(long, value_name = "DIR")]
+    git_dep: Vec<PathBuf>,
     /// Specify a target. Can be given multiple times.
     #[arg(short, long)]
     target: Vec<PathBuf>,
     /// Specify a sentinel. Can be given multiple times.
     #[arg(short, long)]
     sentinel: Vec<PathBuf>,
-    /// Specify the database file to use.
-    #[arg(long, default_value = ".run-if.json")]
-    database: PathBuf,
+    /// Read dependencies from a file, one per line. Use '-' to read from stdin. Can be given multiple times.
+    #[arg(long, value_name = "FILE")]
+    deps_from: Vec<PathBuf>,
+    /// Read targets from a file, one per line. Use '-' to read from stdin. Can be given multiple times.
+    #[arg(long, value_name = "FILE")]
+    targets_from: Vec<PathBuf>,
+    /// Files given to --deps-from and --targets-from are separated by NUL characters instead of newlines
+    /// (i.e. the output of `find -print0`).
+    #[arg(short = '0', long)]
+    null: bool,
     /// Run command no matter what. Result of running command will be saved to database.
     #[arg(short, long)]
     force: bool,
     /// Run command if last run did not exit with status 0.
     #[arg(short = 'u', long)]
     try_until_success: bool,
+    /// Kill the command (and every process it started) if it runs for longer than this (i.e. 30s, 10m).
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    timeout: Option<std::time::Duration>,
+    /// Run the command again, up to this many times, if it fails.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    retries: u32,
+    /// How long to wait before running the command again when it fails (i.e. 5s).
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration, default_value = "0s")]
+    retry_delay: std::time::Duration,
+    /// Store the output of the command (compressed, up to 1 MiB each of stdout and stderr) in the database.
+    /// The command writes to pipes instead of the terminal then, so it may leave out colours.
+    #[arg(long)]
+    capture_output: bool,
+    /// Print the output stored with --capture-output when the command does not need to run.
+    #[arg(long)]
+    replay: bool,
+    /// Write the output of the command to this file too, with a header saying why the command ran
+    /// and a footer with its exit code and how long it ran. Like with --capture-output, the command
+    /// writes to pipes instead of the terminal then.
+    #[arg(long, value_name = "FILE", conflicts_with = "log_dir")]
+    log: Option<PathBuf>,
+    /// Like --log, but write a new log named after the command and the time it started in this directory.
+    #[arg(long, value_name = "DIR")]
+    log_dir: Option<PathBuf>,
     /// Don't do mtime check optimization to detect changes in files, just compare contents.
     #[arg(long)]
     ignore_mtimes: bool,
+    /// Also treat changes to file metadata as dependency changes. Takes a comma separated list of
+    /// attributes to track (mode, uid, gid, xattr). Applies to files inside directory dependencies too.
+    #[arg(long, value_delimiter = ',')]
+    track_metadata: Vec<MetadataField>,
+    /// How to decide if a dependency has changed (mtime, size+mtime, content). `mtime` and `size+mtime`
+    /// only look at file metadata and never read file contents. Give `PATH=MODE` to set the mode for a
+    /// single dependency. Can be given multiple times.
+    #[arg(long, value_name = "[PATH=]MODE", value_parser = parse_per_dependency::<CompareMode>)]
+    compare: Vec<PerDependency<CompareMode>>,
+    /// The hash function to use for dependencies. Changing the hash function will cause commands to
+    /// run once, since the old hashes can no longer be compared.
+    #[arg(long, value_enum, default_value_t = HashAlgorithm::Blake3)]
+    hash: HashAlgorithm,
+    /// Filter the contents of dependencies before hashing them (crlf, trailing-whitespace) so that
+    /// changes to line endings or trailing whitespace are ignored. Give `PATH=FILTER` to filter a
+    /// single dependency. Can be given multiple times.
+    #[arg(long, value_name = "[PATH=]FILTER", value_parser = parse_per_dependency::<TextFilter>)]
+    filter: Vec<PerDependency<TextFilter>>,
+    /// Ignore lines in dependencies that match a regular expression when hashing them. Give
+    /// `PATH=REGEX` to ignore lines in a single dependency, PATH has to be a dependency. Can be
+    /// given multiple times.
+    #[arg(long, value_name = "[PATH=]REGEX")]
+    ignore_lines: Vec<String>,
+    /// Specify an environment variable as a dependency. The command will run if its value changes.
+    /// Can be a glob pattern (i.e. 'CARGO_*') to depend on all matching variables. Can be given multiple times.
+    #[arg(long, value_name = "NAME")]
+    env_dep: Vec<String>,
+    /// Specify a literal value as a dependency, given as `NAME=VALUE`. The command will run if the value
+    /// changes (i.e. --value-dep "version=$(git describe)"). Can be given multiple times.
+    #[arg(long, value_name = "NAME=VALUE", value_parser = parse_name_value)]
+    value_dep: Vec<(String, String)>,
+    /// Read value dependencies from a file containing one `NAME=VALUE` per line. Can be given multiple times.
+    #[arg(long, value_name = "FILE")]
+    value_dep_file: Vec<PathBuf>,
+    /// Specify a command whose output is a dependency (i.e. --cmd-dep "gcc --version"). The command is
+    /// run with `sh -c` and the command will run if its output changes. Can be given multiple times.
+    #[arg(long, value_name = "COMMAND")]
+    cmd_dep: Vec<String>,
+    /// Treat the executable that will be run (the first part of the command, resolved using PATH) as a dependency.
+    #[arg(long)]
+    track_executable: bool,
+    /// Read a Makefile style depfile (i.e. written by `gcc -MD`) after the command runs and treat every
+    /// file it lists as a dependency of the command.
+    #[arg(long, value_name = "FILE")]
+    depfile: Option<PathBuf>,
+    /// Trace the command (Linux only) to discover the files it uses. Every file under the current directory
+    /// the command reads will be treated as a dependency, and every file it writes will be treated as a target.
+    #[arg(long)]
+    trace_deps: bool,
+    /// Run the command once (Linux only), without checking or updating the database, and report the files
+    /// under the current directory it reads that are not covered by a dependency and the files it writes that
+    /// are not covered by a target. Exits with a non-zero status if any are found.
+    #[arg(long)]
+    check_deps: bool,
+    /// Give the command a name so that other commands can depend on it with --after.
+    #[arg(long)]
+    name: Option<String>,
+    /// Depend on another command that was given a name with --name. The command will run if the named
+    /// command has ran since the last time this command ran. Can be given multiple times.
+    #[arg(long, value_name = "NAME")]
+    after: Vec<String>,
+    // a label to print in front of every line of output from the command,
+    // used when several commands run at the same time.
+    #[arg(skip)]
+    output_prefix: Option<String>,
+    /// Run the command once for every file under the dependencies that was added or modified, replacing
+    /// '{}' in the command and targets with the path to the file. Each file is tracked separately,
+    /// so the command can't be given a name with --name or run after other commands with --after.
+    #[arg(long)]
+    each: bool,
+    // the output of each --cmd-dep, if it was already run. --each runs them once for all of the files.
+    #[arg(skip)]
+    cmd_dep_output: Vec<(String, String)>,
+}
 
-    command: Vec<String>,
+// an option value that can either apply to all dependencies or, if given as `PATH=VALUE`,
+// to a single dependency.
+#[derive(Debug, Clone)]
+struct PerDependency<T> {
+    dependency: Option<PathBuf>,
+    value: T,
+}
+
+fn parse_per_dependency<T: ValueEnum>(arg: &str) -> Result<PerDependency<T>, String> {
+    if let Ok(value) = T::from_str(arg, true) {
+        return Ok(PerDependency {
+            dependency: None,
+            value,
+        });
+    }
+    let (dependency, value) = arg
+        .rsplit_once('=')
+        .ok_or(format!("invalid value '{}'", arg))?;
+    return Ok(PerDependency {
+        dependency: Some(dependency.into()),
+        value: T::from_str(value, true)?,
+    });
+}
+
+// get the value that applies to a dependency. values given for the dependency
+// specifically take precedence over values given for all dependencies, and later
+// values take precedence over earlier ones.
+fn value_for_dependency<T: Clone>(values: &[PerDependency<T>], dep: &Path) -> Option<T> {
+    let specific = values
+        .iter()
+        .rev()
+        .find(|v| v.dependency.as_deref() == Some(dep));
+    let general = values.iter().rev().find(|v| v.dependency.is_none());
+    return specific.or(general).map(|v| v.value.clone());
+}
+
+fn parse_name_value(arg: &str) -> Result<(String, String), String> {
+    let (name, value) = arg
+        .split_once('=')
+        .ok_or(format!("expected NAME=VALUE, got '{}'", arg))?;
+    return Ok((name.to_string(), value.to_string()));
+}
+
+// get all of the values that apply to a dependency.
+fn values_for_dependency<T: Clone>(values: &[PerDependency<T>], dep: &Path) -> Vec<T> {
+    return values
+        .iter()
+        .filter(|v| v.dependency.is_none() || v.dependency.as_deref() == Some(dep))
+        .map(|v| v.value.clone())
+        .collect();
+}
+
+// values for a single dependency are checked against the dependencies, so that a typo in
+// the path is an error instead of a regular expression that applies to every dependency.
+fn parse_ignore_lines(
+    args: &[String],
+    dependencies: &[PathBuf],
+) -> Result<Vec<PerDependency<regex::bytes::Regex>>> {
+    let mut ignore_lines = Vec::new();
+    for arg in args.iter() {
+        // the regular expression can contain '=' too, so only split where the part
+        // before it is one of the dependencies.
+        let (dependency, pattern) = match arg
+            .match_indices('=')
+            .map(|(i, _)| (&arg[..i], &arg[i + 1..]))
+            .find(|(dep, _)| dependencies.iter().any(|d| d.as_path() == Path::new(dep)))
+        {
+            Some((dep, pattern)) => (Some(PathBuf::from(dep)), pattern),
+            None => (None, arg.as_str()),
+        };
+        ignore_lines.push(PerDependency {
+            dependency,
+            value: regex::bytes::Regex::new(pattern)
+                .with_context(|| format!("Invalid regular expression '{}'", pattern))?,
+        });
+    }
+    return Ok(ignore_lines);
+}
+
+// make sure that every PATH given for a per-dependency option is one of the dependencies.
+fn check_per_dependency<T>(
+    values: &[PerDependency<T>],
+    dependencies: &[PathBuf],
+    option: &str,
+) -> Result<()> {
+    for dep in values.iter().filter_map(|v| v.dependency.as_ref()) {
+        if !dependencies.iter().any(|d| d == dep) {
+            return Err(anyhow::anyhow!(
+                "'{}' in {} is not a dependency.",
+                dep.display(),
+                option
+            ));
+        }
+    }
+    return Ok(());
+}
+
+// run the command while tracing it and report the files it uses that are not declared.
+fn check_dependencies(
+    command: &[String],
+    dependencies: &[PathBuf],
+    targets: &[PathBuf],
+    database: &Path,
+) -> Result<ExitCode> {
+    debug!("Executing command `{}` to check dependencies.", &command[0]);
+    let mut process = std::process::Command::new(&command[0]);
+    process.args(&command[1..]);
+    let trace = trace::run_traced(&mut process)?;
+    let root = std::fs::canonicalize(".")?;
+    let (reads, writes) = trace.project_files(&root, &[trace::normalize(database)]);
+    let undeclared_dependencies = trace::undeclared(&reads, dependencies, &root);
+    let undeclared_targets = trace::undeclared(&writes, targets, &root);
+
+    for dep in undeclared_dependencies.iter() {
+        eprintln!(
+            "Undeclared dependency: '{}' was read but is not covered by a dependency.",
+            dep.display()
+        );
+    }
+    for tar in undeclared_targets.iter() {
+        eprintln!(
+            "Undeclared target: '{}' was written but is not covered by a target.",
+            tar.display()
+        );
+    }
+    if trace.exit_code != Some(0) {
+        println!("Command did not complete successfully");
+        return Ok(ExitCode::FAILURE);
+    }
+    if !undeclared_dependencies.is_empty() || !undeclared_targets.is_empty() {
+        return Ok(ExitCode::FAILURE);
+    }
+    return Ok(ExitCode::SUCCESS);
 }
 
 fn main() -> Result<ExitCode> {
     env_logger::init();
     let cli = Cli::parse();
-    if cli.command.len() == 0 {
+    process::handle_signals().context("Could not install signal handlers")?;
+    match &cli.subcommand {
+        Some(Subcommand::Run { tasks, file, jobs }) => {
+            return run_tasks(file, tasks, *jobs as usize, &cli.database);
+        }
+        Some(Subcommand::Batch { file, jobs }) => {
+            return run_batch(file, *jobs as usize, &cli.database);
+        }
+        Some(Subcommand::Logs { name }) => {
+            return show_log(name, &cli.database);
+        }
+        Some(Subcommand::Watch { .. }) | None => {}
+    }
+    if let Some(Subcommand::Watch {
+        options,
+        debounce,
+        command,
+    }) = cli.subcommand
+    {
+        return run_watch(*options, command, debounce, &cli.database);
+    }
+    let mut options = cli.options;
+    if cli.command.is_empty() {
         println!("No command given.");
         std::process::exit(0);
     }
 
-    // check if any dependencies and targets are given as arguments.
-    let mut dependencies: Vec<PathBuf> = cli.dependency;
+    let command = split_argument_groups(&mut options, cli.command);
+
+    if options.each {
+        return run_each(&options, &command, &cli.database);
+    }
+
+    if options.check_deps {
+        let (mut declared, targets) = resolve_paths(&options, &command)?;
+        declared.extend(options.git_dep.iter().cloned());
+        if let Some(depfile) = &options.depfile {
+            declared.extend(depfile::parse_file(depfile).unwrap_or_default());
+        }
+        return check_dependencies(&command, &declared, &targets, &cli.database);
+    }
+
+    let (cache, cache_mtime) = load_cache(&cli.database)?;
+    let cache = Mutex::new(cache);
+    let exit_code = run_if(&options, &command, &cache, cache_mtime, &cli.database)?;
+    write_cache(&cli.database, &cache.lock().unwrap())?;
+    return Ok(exit_status(exit_code));
+}
+
+// split the arguments after the options into dependencies, the command, and targets
+// if they are given as argument groups (i.e. `run-if deps... == command... == targets...`).
+fn split_argument_groups(options: &mut Options, args: Vec<String>) -> Vec<String> {
     let mut command: Vec<String> = Vec::new();
-    let mut targets: Vec<PathBuf> = cli.target;
     let delim = "==";
-    if cli.command.iter().any(|e| e == delim) {
+    if args.iter().any(|e| e == delim) {
         let mut c = 0;
-        for item in cli.command.iter() {
+        for item in args.iter() {
             if item == "==" {
                 c += 1;
                 continue;
             }
             if c == 0 {
-                dependencies.push(item.into());
+                options.dependency.push(item.into());
             }
             if c == 1 {
                 command.push(item.into());
             }
             if c == 2 {
-                targets.push(item.into());
+                options.target.push(item.into());
             }
             if c > 2 {
                 eprintln!("Error: too many argument groups. A maximum of 3 groups are allowed which means a maximum of 2 '{}' delimiters are allowed.", delim);
@@ -91,67 +450,222 @@ fn main() -> Result<ExitCode> {
             }
         }
     } else {
-        command = cli.command;
+        command = args;
     }
-    if command.len() == 0 {
+    if command.is_empty() {
         eprintln!("Error: detected argument groups, but command group is empty. There must be at least one argument after the first '{}' delimiter.",delim);
         std::process::exit(1);
     }
+    return command;
+}
+
+// get all of the dependencies and targets for a command, including the ones that are
+// listed in files.
+fn resolve_paths(options: &Options, command: &[String]) -> Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+    let mut dependencies: Vec<PathBuf> = options.dependency.clone();
+    let mut targets: Vec<PathBuf> = options.target.clone();
+    // add dependencies and targets that are listed in files.
+    let stdin_readers = options
+        .deps_from
+        .iter()
+        .chain(options.targets_from.iter())
+        .filter(|f| f.as_os_str() == "-")
+        .count();
+    if stdin_readers > 1 {
+        return Err(anyhow::anyhow!(
+            "stdin ('-') can only be read once by --deps-from and --targets-from."
+        ));
+    }
+    for file in options.deps_from.iter() {
+        dependencies.extend(utils::read_path_list(file, options.null)?);
+    }
+    for file in options.targets_from.iter() {
+        targets.extend(utils::read_path_list(file, options.null)?);
+    }
+
+    if options.track_executable {
+        match utils::find_executable(&command[0]) {
+            Some(executable) => {
+                debug!("Tracking executable '{}'.", executable.display());
+                dependencies.push(executable);
+            }
+            None => {
+                warn!(
+                    "Could not find executable for '{}', it will not be tracked.",
+                    command[0]
+                );
+            }
+        }
+    }
+
+    return Ok((dependencies, targets));
+}
+
+fn load_cache(database: &Path) -> Result<(StatusCache, Option<u128>)> {
     let mut cache = StatusCache::new();
+    // the time the cache was last written. used to detect "racily clean" files.
+    let mut cache_mtime: Option<u128> = None;
     // Check if cache file exists, if so, load it.
-    if cli.database.exists() {
+    if database.exists() {
         // todo: lock the file
         info!(
             "Found database file '{}', attempting to read",
-            cli.database.display()
+            database.display()
         );
-        let data = std::fs::read_to_string(&cli.database)
-            .with_context(|| format!("Could not read file '{}'", cli.database.display()))?;
+        let data = std::fs::read_to_string(database)
+            .with_context(|| format!("Could not read file '{}'", database.display()))?;
         if let Ok(ret) = serde_json::from_str(&data[..]) {
-            cache = ret
+            cache = ret;
+            cache_mtime = Some(change_detection::get_mtime(&database.to_path_buf())?);
         } else {
             warn!(
                 "Database file '{}' seems corrupt. Disregarding",
-                cli.database.display()
+                database.display()
             );
         }
     }
 
+    return Ok((cache, cache_mtime));
+}
+
+// check if a command needs to run and run it if it does. the cache is updated, but not written.
+// returns the exit code of the command, or 0 if it did not need to run.
+fn run_if(
+    options: &Options,
+    command: &[String],
+    cache: &Mutex<StatusCache>,
+    cache_mtime: Option<u128>,
+    database: &Path,
+) -> Result<Option<i32>> {
+    let (mut dependencies, mut targets) = resolve_paths(options, command)?;
+    if options.trace_deps && options.timeout.is_some() {
+        return Err(anyhow::anyhow!(
+            "--timeout can not be used with --trace-deps."
+        ));
+    }
+    if options.trace_deps && options.capture_output {
+        return Err(anyhow::anyhow!(
+            "--capture-output can not be used with --trace-deps."
+        ));
+    }
+    if options.trace_deps && (options.log.is_some() || options.log_dir.is_some()) {
+        return Err(anyhow::anyhow!(
+            "--log and --log-dir can not be used with --trace-deps."
+        ));
+    }
+
     // compute a hash for the command to use as a key in the cache.
-    let cmd_hash = change_detection::hash_string(&command.join(" "));
+    let cmd_hash = command_hash(options.name.as_ref(), command);
     // We assume that the command should not be run
     // because it is _obviously_ expensive
     // (if it wasn't you would not need us).
     let mut run_command = false;
+    // why the command will run, and which dependencies changed. these are passed to the command.
+    let mut reasons: Vec<&str> = Vec::new();
+    let mut changed: Vec<PathBuf> = Vec::new();
+    let mut removed: Vec<PathBuf> = Vec::new();
 
-    // check for command in the cache and create
-    // an empty entry if it does not exist.
-    if !cache.commands.contains_key(&cmd_hash) {
-        // don't run the command just because it has not been ran before.
-        // we want to be able to do something like
-        // 
-        // run-if -t .venv uv venv
-        // 
-        // to run `uv env` only if .venv does not exist, even if it has not been run with run-if
-        // before.
-        // maybe we need an option for this? i.e. --run-once to run if the command has not run yet?
-        // run_command = true;
-        cache
-            .commands
-            .insert(cmd_hash.clone(), CommandStatus::new());
-    }
-    let cmd_status = cache.commands.get_mut(&cmd_hash).unwrap();
+    // work on a copy of the command's status so that the cache is not locked
+    // while the command runs, and put it back when we are done.
+    let (mut cmd_status, upstream_runs) = {
+        let mut cache = cache.lock().unwrap();
+        // check for command in the cache and create
+        // an empty entry if it does not exist.
+        if !cache.commands.contains_key(&cmd_hash) {
+            // don't run the command just because it has not been ran before.
+            // we want to be able to do something like
+            //
+            // run-if -t .venv uv venv
+            //
+            // to run `uv env` only if .venv does not exist, even if it has not been run with run-if
+            // before.
+            // maybe we need an option for this? i.e. --run-once to run if the command has not run yet?
+            // run_command = true;
+            cache
+                .commands
+                .insert(cmd_hash.clone(), CommandStatus::new());
+        }
+        if let Some(name) = &options.name {
+            cache.set_name(&cmd_hash, name);
+        }
+        // get the run counts of the commands we run after. the run count starts over when
+        // the named command changes, so the command's hash is part of it.
+        let mut upstream_runs: Vec<(String, String)> = Vec::new();
+        for name in options.after.iter() {
+            match cache.find_named(name) {
+                Some((upstream_hash, upstream)) => upstream_runs.push((
+                    name.clone(),
+                    format!("{}:{}", upstream_hash, upstream.run_count),
+                )),
+                None => {
+                    return Err(anyhow::anyhow!(
+                        "no command named '{}' found in database '{}'. Run it with --name first.",
+                        name,
+                        database.display()
+                    ));
+                }
+            }
+        }
+        (cache.commands[&cmd_hash].clone(), upstream_runs)
+    };
+
+    // a command that has not been traced yet has no known dependencies or targets,
+    // so nothing would make it run. it has to run once to find them.
+    if options.trace_deps && !cmd_status.traced {
+        debug!("Command has not been traced yet. Command will be executed.");
+        run_command = true;
+        reasons.push("not-traced");
+    }
+
+    // add the dependencies that were discovered the last time the command ran.
+    // unlike dependencies given on the command line, these may disappear
+    // (a header that is no longer included for example), which is a change.
+    if options.depfile.is_some() || options.trace_deps {
+        for dep in cmd_status.discovered_dependencies.iter().map(PathBuf::from) {
+            if dependencies.contains(&dep) {
+                continue;
+            }
+            if !dep.exists() {
+                debug!(
+                    "Discovered dependency '{}' no longer exists. Command will be executed.",
+                    dep.display()
+                );
+                run_command = true;
+                removed.push(dep.clone());
+                reasons.push("dependency-removed");
+                continue;
+            }
+            dependencies.push(dep);
+        }
+    }
+
+    check_per_dependency(&options.compare, &dependencies, "--compare")?;
+    check_per_dependency(&options.filter, &dependencies, "--filter")?;
+    let ignore_lines = parse_ignore_lines(&options.ignore_lines, &dependencies)?;
+    let hash_options_for = |dep: &Path| -> HashOptions {
+        return HashOptions {
+            track_metadata: options.track_metadata.clone(),
+            compare: value_for_dependency(&options.compare, dep).unwrap_or_default(),
+            algorithm: options.hash,
+            filters: values_for_dependency(&options.filter, dep),
+            ignore_lines: values_for_dependency(&ignore_lines, dep),
+        };
+    };
 
     // check to see if any dependencies have changed
     debug!("Checking dependencies...");
     for dep in dependencies.iter() {
         debug!("Checking if dependency '{}' has changed...", dep.display());
         if !dep.exists() {
-            eprintln!("Error: dependency '{}' does not exist.", dep.display());
-            std::process::exit(1);
+            return Err(anyhow::anyhow!(
+                "dependency '{}' does not exist.",
+                dep.display()
+            ));
         }
         let dep_name = dep.to_string_lossy().into_owned();
-        let dep_mtime = change_detection::get_mtime(&dep)?;
+        let hash_options = hash_options_for(dep);
+        let dep_stat = change_detection::get_stat(dep)?;
+        let dep_metadata_hash = change_detection::hash_metadata(dep, &hash_options)?;
 
         if !cmd_status.dependencies.contains_key(&dep_name) {
             // is dependency in the cache?
@@ -161,46 +675,75 @@ fn main() -> Result<ExitCode> {
             );
 
             run_command = true;
-            let dep_hash = change_detection::hash_path(dep)?;
-            cmd_status.dependencies.insert(
-                dep_name.clone(),
-                DependencyStatus {
-                    content_hash: dep_hash,
-                    mtime: dep_mtime,
-                },
-            );
+            changed.push(dep.clone());
+            reasons.push("dependency-added");
+            let dep_hash = change_detection::hash_path(dep, &hash_options)?;
+            let mut dep_status = DependencyStatus::new(dep_hash, &dep_stat);
+            dep_status.metadata_hash = dep_metadata_hash;
+            cmd_status.dependencies.insert(dep_name.clone(), dep_status);
         } else {
             debug!("  Found '{}' in cache.", dep.display(),);
             debug!("  Checking if '{}' has been modified...", dep.display(),);
-            debug!("  Current mtime: {}", dep_mtime);
+            debug!("  Current mtime: {}", dep_stat.mtime);
             debug!(
                 "  Cached  mtime: {}",
                 cmd_status.dependencies.get(&dep_name).unwrap().mtime
             );
             // optimization: for files, check if file has been "modified" (saved) since last time.
+            // the size, inode, and ctime are checked too, and files that were modified too close
+            // to the time the cache was written are always checked, because their mtime might
+            // not have changed even though their contents did.
+            // the cheap compare modes only hash stat information, so there is nothing to save.
+            // and if the hash algorithm changed, the cached hash is useless.
             if dep.is_dir()
-                || cli.ignore_mtimes
-                || cmd_status.dependencies.get(&dep_name).unwrap().mtime != dep_mtime
+                || options.ignore_mtimes
+                || hash_options.compare != CompareMode::Content
+                || !cmd_status
+                    .dependencies
+                    .get(&dep_name)
+                    .unwrap()
+                    .hashed_with(&hash_options)
+                || !cmd_status
+                    .dependencies
+                    .get(&dep_name)
+                    .unwrap()
+                    .stat_matches(&dep_stat)
+                || cmd_status
+                    .dependencies
+                    .get(&dep_name)
+                    .unwrap()
+                    .is_racy(cache_mtime)
             {
-                debug!("  '{}' has been modified.", dep.display(),);
-                cmd_status.dependencies.get_mut(&dep_name).unwrap().mtime = dep_mtime;
+                debug!("  '{}' has (possibly) been modified.", dep.display(),);
+                cmd_status
+                    .dependencies
+                    .get_mut(&dep_name)
+                    .unwrap()
+                    .update_stat(&dep_stat);
                 debug!(
                     "  Checking if contents of '{}' have changed...",
                     dep.display(),
                 );
                 // check if file has _actually_ been modified
-                let dep_hash = change_detection::hash_path(dep)?;
+                let dep_hash = change_detection::hash_path(dep, &hash_options)?;
                 debug!("  Current hash: {}", dep_hash);
                 debug!(
                     "  Cached  hash: {}",
                     cmd_status.dependencies.get(&dep_name).unwrap().content_hash
                 );
-                if cmd_status.dependencies.get(&dep_name).unwrap().content_hash != dep_hash {
+                if !cmd_status
+                    .dependencies
+                    .get(&dep_name)
+                    .unwrap()
+                    .hash_matches(&dep_hash)
+                {
                     debug!(
                         "  '{}' contents have changed. Command will be executed.",
                         dep.display(),
                     );
                     run_command = true;
+                    changed.push(dep.clone());
+                    reasons.push("dependency-changed");
                     cmd_status
                         .dependencies
                         .get_mut(&dep_name)
@@ -212,11 +755,166 @@ fn main() -> Result<ExitCode> {
             } else {
                 debug!("  '{}' has not changed.", dep.display());
             }
+            // metadata changes do not update the mtime, so they have to be checked separately.
+            if cmd_status
+                .dependencies
+                .get(&dep_name)
+                .unwrap()
+                .metadata_hash
+                != dep_metadata_hash
+            {
+                debug!(
+                    "  '{}' metadata has changed. Command will be executed.",
+                    dep.display(),
+                );
+                run_command = true;
+                changed.push(dep.clone());
+                reasons.push("dependency-changed");
+                cmd_status
+                    .dependencies
+                    .get_mut(&dep_name)
+                    .unwrap()
+                    .metadata_hash = dep_metadata_hash;
+            }
+        }
+    }
+
+    // check to see if any files tracked by git have changed
+    debug!("Checking git dependencies...");
+    for dir in options.git_dep.iter() {
+        if !dir.is_dir() {
+            return Err(anyhow::anyhow!(
+                "git dependency '{}' is not a directory.",
+                dir.display()
+            ));
+        }
+        let dir_hash = change_detection::hash_git_tracked(dir, &hash_options_for(dir))?;
+        if cmd_status.update_dependency(&format!("git:{}", dir.display()), dir_hash) {
+            debug!(
+                "  files tracked by git in '{}' have changed. Command will be executed.",
+                dir.display()
+            );
+            run_command = true;
+            changed.push(dir.clone());
+            reasons.push("dependency-changed");
+        } else {
+            debug!(
+                "  files tracked by git in '{}' have not changed.",
+                dir.display()
+            );
+        }
+    }
+
+    // dependencies that are not files just need a hash of their value.
+    let value_options = HashOptions {
+        algorithm: options.hash,
+        ..Default::default()
+    };
+
+    // check to see if any environment variables have changed
+    debug!("Checking environment variables...");
+    for pattern in options.env_dep.iter() {
+        let matcher = glob::Pattern::new(pattern)
+            .with_context(|| format!("Invalid environment variable pattern '{}'", pattern))?;
+        let value = std::env::vars_os()
+            .map(|(k, v)| {
+                (
+                    k.to_string_lossy().into_owned(),
+                    v.to_string_lossy().into_owned(),
+                )
+            })
+            .filter(|(k, _)| matcher.matches(k))
+            .sorted()
+            .map(|(k, v)| format!("{}={}\n", k, v))
+            .collect::<String>();
+        let value_hash = change_detection::hash_value(&value, &value_options);
+        if cmd_status.update_dependency(&format!("env:{}", pattern), value_hash) {
+            debug!(
+                "  environment variable '{}' has changed. Command will be executed.",
+                pattern
+            );
+            run_command = true;
+            reasons.push("environment-changed");
+        } else {
+            debug!("  environment variable '{}' has not changed.", pattern);
+        }
+    }
+
+    // check to see if any values have changed
+    debug!("Checking values...");
+    let mut values = options.value_dep.clone();
+    for file in options.value_dep_file.iter() {
+        let data = std::fs::read_to_string(file)
+            .with_context(|| format!("Could not read file '{}'", file.display()))?;
+        for line in data.lines().filter(|l| !l.trim().is_empty()) {
+            match parse_name_value(line) {
+                Ok(value) => values.push(value),
+                Err(e) => {
+                    return Err(anyhow::anyhow!(
+                        "invalid value dependency in '{}': {}",
+                        file.display(),
+                        e
+                    ));
+                }
+            }
+        }
+    }
+    for (name, value) in values.iter() {
+        let value_hash = change_detection::hash_value(value, &value_options);
+        if cmd_status.update_dependency(&format!("value:{}", name), value_hash) {
+            debug!("  value '{}' has changed. Command will be executed.", name);
+            run_command = true;
+            reasons.push("value-changed");
+        } else {
+            debug!("  value '{}' has not changed.", name);
+        }
+    }
+
+    // check to see if the output of any commands has changed
+    debug!("Checking command outputs...");
+    for probe in options.cmd_dep.iter() {
+        let output = match options.cmd_dep_output.iter().find(|(p, _)| p == probe) {
+            Some((_, output)) => output.clone(),
+            None => run_dependency_command(probe)?,
+        };
+        let output_hash = change_detection::hash_value(&output, &value_options);
+        if cmd_status.update_dependency(&format!("cmd:{}", probe), output_hash) {
+            debug!(
+                "  output of '{}' has changed. Command will be executed.",
+                probe
+            );
+            run_command = true;
+            reasons.push("command-output-changed");
+        } else {
+            debug!("  output of '{}' has not changed.", probe);
+        }
+    }
+
+    // check to see if any of the commands we run after have ran
+    debug!("Checking upstream commands...");
+    for (name, run) in upstream_runs.iter() {
+        let run_hash = change_detection::hash_value(run, &value_options);
+        if cmd_status.update_dependency(&format!("after:{}", name), run_hash) {
+            debug!(
+                "  command '{}' has ran since last time. Command will be executed.",
+                name
+            );
+            run_command = true;
+            reasons.push("upstream-ran");
+        } else {
+            debug!("  command '{}' has not ran since last time.", name);
         }
     }
 
     // check to see if any targets are missing
     debug!("Checking targets...");
+    if options.trace_deps {
+        for tar in cmd_status.discovered_targets.iter().map(PathBuf::from) {
+            if !targets.contains(&tar) {
+                targets.push(tar);
+            }
+        }
+    }
     for tar in targets.iter() {
         if !tar.exists() {
             debug!(
@@ -224,6 +922,7 @@ fn main() -> Result<ExitCode> {
                 tar.display()
             );
             run_command = true;
+            reasons.push("target-missing");
             break;
         } else {
             debug!("  target '{}' exists.", tar.display());
@@ -231,72 +930,727 @@ fn main() -> Result<ExitCode> {
     }
     // check to see if any sentinels exist
     debug!("Checking sentinels...");
-    for sen in cli.sentinel.iter() {
+    for sen in options.sentinel.iter() {
         debug!(
             "  sentinel '{}' exists. Command will be executed.",
             sen.display()
         );
         if sen.exists() {
             run_command = true;
+            reasons.push("sentinel-exists");
             break;
         } else {
             debug!("  sentinel '{}' does not exist.", sen.display());
         }
     }
 
-    if cli.force {
+    if options.force {
         run_command = true;
+        reasons.push("forced");
         debug!("--force flag was given. Command will be executed.");
     }
 
     if !run_command {
-        if cli.try_until_success {
-            if cmd_status.exit_code.unwrap() != 0 {
+        if options.try_until_success {
+            if cmd_status.failed() {
                 debug!("Command returned non-zero exit code last time and --try-until-success was given. Command will be executed.");
                 run_command = true;
+                reasons.push("previous-failure");
             }
         }
     }
 
+    if !run_command && cmd_status.unfinished() {
+        debug!("The last run of the command did not finish. Command will be executed.");
+        run_command = true;
+        reasons.push("previous-failure");
+    }
+
     let mut exit_code: Option<i32> = Some(0);
     if run_command {
         debug!("Executing command `{}`.", &command[0]);
-        let status = std::process::Command::new(&command[0])
-            .args(&command[1..])
-            .status()
-            .expect(&format!(
-                "Error executing the command. Command parts: {:?}",
-                command
+        // tell the command what changed, so that it can do only the work that is needed.
+        let join_paths = |paths: &[PathBuf]| -> String {
+            return paths
+                .iter()
+                .unique()
+                .map(|p| p.to_string_lossy().into_owned())
+                .join("\n");
+        };
+        // when the command runs for another reason (i.e. it was forced, or a target is missing),
+        // nothing changed, so it gets every dependency instead.
+        let changed_args: Vec<String> = if changed.is_empty() {
+            &dependencies
+        } else {
+            &changed
+        }
+        .iter()
+        .unique()
+        .map(|p| p.to_string_lossy().into_owned())
+        .collect();
+        let args: Vec<String> = command
+            .iter()
+            .flat_map(|arg| {
+                if arg == "{changed}" {
+                    return changed_args.clone();
+                }
+                return vec![arg.clone()];
+            })
+            .collect();
+        if args.is_empty() {
+            return Err(anyhow::anyhow!(
+                "the command is empty after replacing '{{changed}}', there are no dependencies."
             ));
-        exit_code = status.code();
-        cmd_status.exit_code = status.code();
+        }
+        // every attempt needs a new process.
+        let new_process = || -> std::process::Command {
+            let mut process = std::process::Command::new(&args[0]);
+            process
+                .args(&args[1..])
+                .env("RUN_IF_REASON", reasons.iter().unique().join(","))
+                .env("RUN_IF_CHANGED", join_paths(&changed))
+                .env("RUN_IF_REMOVED", join_paths(&removed));
+            return process;
+        };
+        let mut discovered: Vec<PathBuf> = Vec::new();
+        let mut captured: Option<CapturedOutput> = None;
+        let started = std::time::SystemTime::now();
+        let mut run = RunRecord {
+            started: started.duration_since(std::time::UNIX_EPOCH)?.as_secs(),
+            reasons: reasons.iter().unique().map(|r| r.to_string()).collect(),
+            attempts: Vec::new(),
+        };
+        let log_path = match (&options.log, &options.log_dir) {
+            (Some(file), _) => Some(file.clone()),
+            (None, Some(dir)) => {
+                let label = options.name.as_deref().unwrap_or(&cmd_hash[..12]);
+                Some(logfile::path_in_dir(dir, label, started))
+            }
+            (None, None) => None,
+        };
+        let log = match &log_path {
+            Some(path) => {
+                let reasons: Vec<&str> = reasons.iter().unique().copied().collect();
+                let changed: Vec<PathBuf> = changed.iter().unique().cloned().collect();
+                Some(logfile::create(path, command, started, &reasons, &changed)?)
+            }
+            None => None,
+        };
+        // a log from an earlier run would be mistaken for the log of this one.
+        cmd_status.log = match &log_path {
+            Some(path) => Some(std::path::absolute(path)?.to_string_lossy().into_owned()),
+            None => None,
+        };
+        for attempt in 0..=options.retries {
+            if attempt > 0 {
+                let message = format!(
+                    "Command did not succeed, trying again ({} of {}).",
+                    attempt, options.retries
+                );
+                println!("{}", message);
+                if let Some(log) = &log {
+                    logfile::note(log, &message)?;
+                }
+                std::thread::sleep(options.retry_delay);
+            }
+            let started = std::time::Instant::now();
+            let outcome = if options.trace_deps {
+                let _running = process::Running::start();
+                let trace = trace::run_traced(&mut new_process())?;
+                let (trace_dependencies, trace_targets) = trace
+                    .project_files(&std::fs::canonicalize(".")?, &[trace::normalize(database)]);
+                discovered = trace_dependencies;
+                cmd_status.discovered_targets = trace_targets
+                    .iter()
+                    .map(|t| t.to_string_lossy().into_owned())
+                    .collect();
+                cmd_status.traced = true;
+                match trace.exit_code {
+                    _ if trace.interrupted => Outcome::Interrupted,
+                    Some(code) => Outcome::Exited(code),
+                    None => Outcome::Signaled,
+                }
+            } else {
+                let (outcome, output) = process::run(
+                    &mut new_process(),
+                    options.output_prefix.as_deref(),
+                    options.timeout,
+                    options.capture_output,
+                    log.as_ref(),
+                )
+                .with_context(|| format!("Could not run command '{}'", args[0]))?;
+                // only the output of the last attempt is kept.
+                captured = match output {
+                    Some(output) => Some(CapturedOutput::new(
+                        &output.stdout,
+                        &output.stderr,
+                        output.truncated,
+                    )?),
+                    None => None,
+                };
+                outcome
+            };
+            if outcome == Outcome::TimedOut {
+                println!(
+                    "Command timed out after {}",
+                    humantime::format_duration(options.timeout.unwrap_or_default())
+                );
+            }
+            run.attempts.push(Attempt {
+                outcome,
+                duration_ms: started.elapsed().as_millis() as u64,
+            });
+            if outcome.success() || outcome == Outcome::Interrupted {
+                break;
+            }
+        }
+        exit_code = run.attempts.last().and_then(|a| a.outcome.exit_code());
+        if let (Some(log), Some(last)) = (&log, run.attempts.last()) {
+            logfile::finish(log, last.outcome, started.elapsed().unwrap_or_default())?;
+        }
+        cmd_status.record_run(run);
+        // output from an earlier run would be misleading, so it is dropped if this run was not captured.
+        cmd_status.output = captured;
+
+        // if the depfile can't be read, the dependencies it listed are unknown. the ones
+        // from the last run are dropped rather than kept, since they could be out of date.
+        if let Some(depfile) = &options.depfile {
+            if depfile.exists() {
+                debug!("Reading depfile '{}'.", depfile.display());
+                match depfile::parse_file(depfile) {
+                    Ok(deps) => discovered.extend(deps),
+                    Err(e) => warn!("{:#}", e),
+                }
+            } else {
+                warn!(
+                    "Depfile '{}' does not exist after running the command.",
+                    depfile.display()
+                );
+            }
+        }
+        if options.depfile.is_some() || options.trace_deps {
+            cmd_status.discovered_dependencies = Vec::new();
+            for dep in discovered.iter().unique().filter(|d| d.exists()) {
+                debug!("  Recording discovered dependency '{}'.", dep.display());
+                let dep_name = dep.to_string_lossy().into_owned();
+                let hash_options = hash_options_for(dep);
+                let mut dep_status = DependencyStatus::new(
+                    change_detection::hash_path(dep, &hash_options)?,
+                    &change_detection::get_stat(dep)?,
+                );
+                dep_status.metadata_hash = change_detection::hash_metadata(dep, &hash_options)?;
+                cmd_status.dependencies.insert(dep_name.clone(), dep_status);
+                cmd_status.discovered_dependencies.push(dep_name);
+            }
+        }
+    } else if options.replay {
+        if let Some(output) = &cmd_status.output {
+            debug!("Replaying the output of the last run.");
+            replay_output(output, options.output_prefix.as_deref())?;
+        }
     }
+    cache.lock().unwrap().commands.insert(cmd_hash, cmd_status);
+    return Ok(exit_code);
+}
+
+// the key of a command in the cache. the same command can be given different
+// names (two tasks that run it for example), and each name needs its own entry.
+fn command_hash(name: Option<&String>, command: &[String]) -> String {
+    return match name {
+        Some(name) => change_detection::hash_string(&format!("{}\n{}", name, command.join(" "))),
+        None => change_detection::hash_string(&command.join(" ")),
+    };
+}
+
+// run a command given with --cmd-dep and get its output.
+fn run_dependency_command(probe: &str) -> Result<String> {
+    debug!("  Running '{}'...", probe);
+    let output = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(probe)
+        .stderr(std::process::Stdio::inherit())
+        .output()
+        .with_context(|| format!("Could not run dependency command '{}'", probe))?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "dependency command '{}' did not complete successfully.",
+            probe
+        ));
+    }
+    return Ok(String::from_utf8_lossy(&output.stdout).into_owned());
+}
+
+// print the output that was captured the last time a command ran.
+fn replay_output(output: &CapturedOutput, prefix: Option<&str>) -> Result<()> {
+    use std::io::Write;
+
+    let with_prefix = |data: Vec<u8>| -> Vec<u8> {
+        let Some(prefix) = prefix else {
+            return data;
+        };
+        return data
+            .split_inclusive(|b| *b == b'\n')
+            .flat_map(|line| {
+                let mut line = [prefix.as_bytes(), line].concat();
+                if !line.ends_with(b"\n") {
+                    line.push(b'\n');
+                }
+                return line;
+            })
+            .collect();
+    };
+    std::io::stdout()
+        .lock()
+        .write_all(&with_prefix(output.stdout()?))?;
+    std::io::stderr()
+        .lock()
+        .write_all(&with_prefix(output.stderr()?))?;
+    if output.truncated {
+        eprintln!("(output was truncated)");
+    }
+    return Ok(());
+}
+
+// print the log of the last run of a named command.
+fn show_log(name: &str, database: &Path) -> Result<ExitCode> {
+    let (cache, _) = load_cache(database)?;
+    let Some((_, cmd_status)) = cache.find_named(name) else {
+        eprintln!(
+            "Error: no command named '{}' found in database '{}'.",
+            name,
+            database.display()
+        );
+        std::process::exit(1);
+    };
+    let Some(log) = &cmd_status.log else {
+        eprintln!(
+            "Error: '{}' has no log. Run it with --log or --log-dir first.",
+            name
+        );
+        std::process::exit(1);
+    };
+    let data = std::fs::read(log).with_context(|| format!("Could not read log file '{}'", log))?;
+    std::io::Write::write_all(&mut std::io::stdout().lock(), &data)?;
+    return Ok(ExitCode::SUCCESS);
+}
+
+fn write_cache(database: &Path, cache: &StatusCache) -> Result<()> {
     // write the cache file even if we didn't run the command
     // because some things like file modification time, command exist status, etc, may have
     // changed.
     // TODO: lock file
-    let fout = std::fs::File::create(&cli.database).with_context(|| {
+    let fout = std::fs::File::create(database).with_context(|| {
         format!(
             "Could not open database file '{}' for writing.",
-            cli.database.display()
+            database.display()
         )
     })?;
-    serde_json::to_writer(fout, &cache)?;
+    serde_json::to_writer(fout, cache)?;
+
+    return Ok(());
+}
 
+fn exit_status(exit_code: Option<i32>) -> ExitCode {
+    if let Some(signal) = process::interrupted_by() {
+        println!("Command was interrupted");
+        return ExitCode::from((128 + signal) as u8);
+    }
     // if &cmd_status.exit_code.unwrap() != 0 {
     //     return Ok(ExitCode::from(1));
     // }
+    report_failure(exit_code);
+    if exit_code == Some(0) {
+        return ExitCode::SUCCESS;
+    }
+    return ExitCode::FAILURE;
+}
+
+// tell the user if the command did not succeed.
+fn report_failure(exit_code: Option<i32>) {
     match exit_code {
-        Some(code) => {
-            if code == 0 {
-                return Ok(ExitCode::SUCCESS);
+        Some(0) => {}
+        Some(code) => println!("Command returned non-zero exit status {}", code),
+        None => println!("Command did not complete successfully"),
+    }
+}
+
+// a command that runs as part of a group of commands,
+// i.e. a task from a task file or an entry in a batch file.
+struct Job {
+    label: String,
+    options: Options,
+    command: Vec<String>,
+    // the labels of the jobs that have to finish successfully before this one starts.
+    needs: Vec<String>,
+}
+
+// run a group of jobs, up to `max_jobs` at the same time. a job only starts after all of the jobs
+// it needs have finished successfully. if `keep_going` is false, no new jobs are started after a job
+// fails. returns the exit code of the first job that failed, if any did. errors in jobs (i.e. a
+// missing dependency) count as failures if `keep_going` is true, otherwise the first one is returned
+// after the jobs that are running have finished, so the caller can still write their results.
+fn run_jobs(
+    jobs: &[Job],
+    max_jobs: usize,
+    keep_going: bool,
+    cache: &Mutex<StatusCache>,
+    cache_mtime: Option<u128>,
+    database: &Path,
+) -> Result<Option<Option<i32>>> {
+    if max_jobs > 1 && jobs.iter().any(|j| j.options.trace_deps) {
+        return Err(anyhow::anyhow!(
+            "--trace-deps can not be used when running more than one command at a time."
+        ));
+    }
+    let mut started: HashSet<&String> = HashSet::new();
+    let mut finished: HashSet<&String> = HashSet::new();
+    let mut succeeded: HashSet<&String> = HashSet::new();
+    let mut failure: Option<Option<i32>> = None;
+    let mut error: Option<anyhow::Error> = None;
+    std::thread::scope(|scope| {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        // every job gets a clone of the sender. ours is dropped once no more jobs will be
+        // started, so that waiting for results can't block forever.
+        let mut sender = Some(sender);
+        loop {
+            if error.is_none()
+                && (keep_going || failure.is_none())
+                && process::interrupted_by().is_none()
+            {
+                for job in jobs.iter() {
+                    if started.len() - finished.len() >= max_jobs {
+                        break;
+                    }
+                    if started.contains(&job.label)
+                        || !job.needs.iter().all(|n| succeeded.contains(n))
+                    {
+                        continue;
+                    }
+                    info!("Running '{}'.", job.label);
+                    started.insert(&job.label);
+                    let mut options = job.options.clone();
+                    if max_jobs > 1 {
+                        options.output_prefix = Some(format!("[{}] ", job.label));
+                    }
+                    let Some(sender) = sender.clone() else {
+                        break;
+                    };
+                    scope.spawn(move || {
+                        // a panic is reported like any other error, so the other jobs still finish.
+                        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                            return run_if(&options, &job.command, cache, cache_mtime, database);
+                        }))
+                        .unwrap_or_else(|_| Err(anyhow::anyhow!("'{}' panicked.", job.label)));
+                        let _ = sender.send((&job.label, result));
+                    });
+                }
+            }
+            if started.len() == jobs.len() || error.is_some() || (!keep_going && failure.is_some())
+            {
+                sender = None;
+            }
+            if started.len() == finished.len() {
+                break;
+            }
+            let Ok((label, result)) = receiver.recv() else {
+                break;
+            };
+            finished.insert(label);
+            match result {
+                Ok(Some(0)) => {
+                    succeeded.insert(label);
+                }
+                Ok(exit_code) => {
+                    eprintln!("Error: '{}' failed.", label);
+                    failure.get_or_insert(exit_code);
+                }
+                Err(e) if keep_going => {
+                    eprintln!("Error: '{}' failed: {:#}", label, e);
+                    failure.get_or_insert(Some(1));
+                }
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
             }
-            println!("Command returned non-zero exit status {}", code);
-            return Ok(ExitCode::FAILURE);
         }
-        None => {
-            println!("Command did not complete successfully");
-            return Ok(ExitCode::FAILURE);
+    });
+    if let Some(e) = error {
+        return Err(e);
+    }
+    return Ok(failure);
+}
+
+// run a command once for each file under the dependencies, like a change aware `xargs`.
+// every file gets its own entry in the cache because the command is different for each one.
+fn run_each(options: &Options, command: &[String], database: &Path) -> Result<ExitCode> {
+    if !command.iter().any(|arg| arg.contains("{}")) {
+        eprintln!("Error: --each requires a '{{}}' placeholder in the command.");
+        std::process::exit(1);
+    }
+    // every file gets its own entry in the database, but a name can only belong to one of them.
+    if options.name.is_some() || !options.after.is_empty() {
+        eprintln!("Error: --name and --after can not be used with --each.");
+        std::process::exit(1);
+    }
+    let (dependencies, targets) = resolve_paths(options, command)?;
+    let fs = utils::FileSearch::new();
+    let mut files: Vec<PathBuf> = Vec::new();
+    for dep in dependencies.iter() {
+        if !dep.exists() {
+            eprintln!("Error: dependency '{}' does not exist.", dep.display());
+            std::process::exit(1);
         }
+        if dep.is_dir() {
+            files.extend(fs.get_all_files_under(dep)?.into_iter().sorted());
+        } else {
+            files.push(dep.clone());
+        }
+    }
+
+    // the dependency commands are the same for every file, so they only run once.
+    let mut cmd_dep_output = Vec::new();
+    for probe in options.cmd_dep.iter() {
+        cmd_dep_output.push((probe.clone(), run_dependency_command(probe)?));
     }
+
+    let substitute = |arg: &str, file: &str| -> String { arg.replace("{}", file) };
+    let mut jobs: Vec<Job> = Vec::new();
+    for file in files.iter().unique() {
+        let file_name = file.to_string_lossy();
+        let mut file_options = options.clone();
+        file_options.each = false;
+        file_options.dependency = vec![file.clone()];
+        // the paths from files were already read, and stdin can only be read once.
+        file_options.deps_from = Vec::new();
+        file_options.targets_from = Vec::new();
+        file_options.cmd_dep_output = cmd_dep_output.clone();
+        file_options.target = targets
+            .iter()
+            .map(|t| PathBuf::from(substitute(&t.to_string_lossy(), &file_name)))
+            .collect();
+        jobs.push(Job {
+            label: file_name.to_string(),
+            options: file_options,
+            command: command.iter().map(|a| substitute(a, &file_name)).collect(),
+            needs: Vec::new(),
+        });
+    }
+
+    let (cache, cache_mtime) = load_cache(database)?;
+    let cache = Mutex::new(cache);
+    let failure = run_jobs(&jobs, 1, true, &cache, cache_mtime, database);
+    // forget the files that are no longer under the dependencies.
+    let each_hash = command_hash(None, command);
+    let file_hashes: Vec<String> = jobs
+        .iter()
+        .map(|j| command_hash(None, &j.command))
+        .collect();
+    {
+        let mut cache = cache.lock().unwrap();
+        cache.commands.retain(|hash, status| {
+            status.each.as_deref() != Some(each_hash.as_str()) || file_hashes.contains(hash)
+        });
+        for hash in file_hashes.iter() {
+            if let Some(status) = cache.commands.get_mut(hash) {
+                status.each = Some(each_hash.clone());
+            }
+        }
+        write_cache(database, &cache)?;
+    }
+    return match failure? {
+        Some(exit_code) => Ok(exit_status(exit_code)),
+        None => Ok(ExitCode::SUCCESS),
+    };
+}
+
+// check the command, and check it again every time a dependency or target changes. runs until killed.
+fn run_watch(
+    mut options: Options,
+    args: Vec<String>,
+    debounce: u64,
+    database: &Path,
+) -> Result<ExitCode> {
+    let command = split_argument_groups(&mut options, args);
+    if options.check_deps {
+        eprintln!("Error: --check-deps can not be used with watch.");
+        std::process::exit(1);
+    }
+    // paths from files (and stdin) can only be read once.
+    let (dependencies, targets) = resolve_paths(&options, &command)?;
+    options.dependency = dependencies.clone();
+    options.target = targets.clone();
+    options.deps_from = Vec::new();
+    options.targets_from = Vec::new();
+    options.track_executable = false;
+
+    let watched_dependencies = dependencies
+        .iter()
+        .chain(options.git_dep.iter())
+        .map(|d| watch::absolute(d))
+        .collect::<Result<Vec<PathBuf>>>()?;
+    let watched_targets = targets
+        .iter()
+        .map(|t| watch::absolute(t))
+        .collect::<Result<Vec<PathBuf>>>()?;
+    // the database is written every time the command is checked, which must not count as a change.
+    let mut watch = watch::Watch::new(
+        &watched_dependencies,
+        &watched_targets,
+        &[watch::absolute(database)?],
+        std::time::Duration::from_millis(debounce),
+    )?;
+    loop {
+        // files are often deleted and created again when they are saved,
+        // so a missing dependency is not an error here.
+        match dependencies.iter().find(|d| !d.exists()) {
+            Some(dep) => {
+                eprintln!("Waiting for dependency '{}' to exist.", dep.display());
+            }
+            // signals only reach us while a command runs. one that interrupted
+            // the command stops watching too.
+            None if options.each => {
+                let status = run_each(&options, &command, database)?;
+                if process::interrupted_by().is_some() {
+                    return Ok(status);
+                }
+            }
+            None => {
+                let (cache, cache_mtime) = load_cache(database)?;
+                let cache = Mutex::new(cache);
+                let exit_code = run_if(&options, &command, &cache, cache_mtime, database)?;
+                let cache = cache.into_inner().unwrap();
+                write_cache(database, &cache)?;
+                if process::interrupted_by().is_some() {
+                    return Ok(exit_status(exit_code));
+                }
+                report_failure(exit_code);
+                // dependencies and targets found by reading a depfile or tracing
+                // the command are only known after it has run.
+                if let Some(cmd_status) = cache
+                    .commands
+                    .get(&command_hash(options.name.as_ref(), &command))
+                {
+                    let discovered = cmd_status
+                        .discovered_dependencies
+                        .iter()
+                        .chain(cmd_status.discovered_targets.iter())
+                        .map(|p| watch::absolute(Path::new(p)))
+                        .collect::<Result<Vec<PathBuf>>>()?;
+                    // they could have changed before they were watched, so check again.
+                    if watch.add(&discovered)? {
+                        continue;
+                    }
+                }
+            }
+        }
+        watch.wait()?;
+    }
+}
+
+// the options for a command from a task file or a batch file.
+fn task_options(name: Option<&String>, task: &tasks::Task) -> Options {
+    return Options {
+        dependency: task.deps.clone(),
+        target: task.targets.clone(),
+        sentinel: task.sentinels.clone(),
+        git_dep: task.git_deps.clone(),
+        env_dep: task.env_deps.clone(),
+        cmd_dep: task.cmd_deps.clone(),
+        depfile: task.depfile.clone(),
+        name: name.cloned(),
+        // a task runs again when a task it needs has ran.
+        after: task.needs.clone(),
+        ..Default::default()
+    };
+}
+
+// run tasks from a task file, and the tasks they need. up to `jobs` tasks run at the same
+// time, but a task only starts after all of the tasks it needs have finished.
+// no new tasks are started after a task fails.
+fn run_tasks(file: &Path, requested: &[String], jobs: usize, database: &Path) -> Result<ExitCode> {
+    let task_file = tasks::load(file)?;
+    let order: Vec<Job> = task_file
+        .order(requested)?
+        .into_iter()
+        .map(|name| {
+            let task = &task_file.tasks[&name];
+            return Job {
+                options: task_options(Some(&name), task),
+                command: task.command.args(),
+                needs: task.needs.clone(),
+                label: name,
+            };
+        })
+        .collect();
+
+    let (cache, cache_mtime) = load_cache(database)?;
+    let cache = Mutex::new(cache);
+    let failure = run_jobs(&order, jobs, false, &cache, cache_mtime, database);
+    write_cache(database, &cache.lock().unwrap())?;
+    return match failure? {
+        Some(exit_code) => Ok(exit_status(exit_code)),
+        None => Ok(ExitCode::SUCCESS),
+    };
+}
+
+// the arguments of a line in a batch file are parsed like the command line.
+#[derive(Parser)]
+#[command(no_binary_name = true)]
+struct BatchLine {
+    #[command(flatten)]
+    options: Options,
+    command: Vec<String>,
+}
+
+// run every command in a batch file. the database is only read and written once, and
+// every command is checked even if an earlier one fails.
+fn run_batch(file: &Path, jobs: usize, database: &Path) -> Result<ExitCode> {
+    let contents = if file.as_os_str() == "-" {
+        std::io::read_to_string(std::io::stdin()).context("Could not read batch from stdin")?
+    } else {
+        std::fs::read_to_string(file)
+            .with_context(|| format!("Could not read batch file '{}'", file.display()))?
+    };
+    let mut entries: Vec<Job> = Vec::new();
+    for (line_number, entry) in batch::parse(&contents)? {
+        let invalid = |e: &dyn std::fmt::Display| {
+            eprintln!(
+                "Error: invalid entry on line {} of '{}': {}",
+                line_number,
+                file.display(),
+                e
+            );
+            std::process::exit(1);
+        };
+        let (options, command) = match entry {
+            batch::Entry::Record(task) => (task_options(None, &task), task.command.args()),
+            batch::Entry::Args(args) => {
+                let line = BatchLine::try_parse_from(args).unwrap_or_else(|e| invalid(&e));
+                let mut options = line.options;
+                if options.check_deps {
+                    invalid(&"--check-deps can not be used in a batch");
+                }
+                if line.command.is_empty() {
+                    invalid(&"no command given");
+                }
+                let command = split_argument_groups(&mut options, line.command);
+                (options, command)
+            }
+        };
+        entries.push(Job {
+            label: line_number.to_string(),
+            options,
+            command,
+            needs: Vec::new(),
+        });
+    }
+
+    let (cache, cache_mtime) = load_cache(database)?;
+    let cache = Mutex::new(cache);
+    let failure = run_jobs(&entries, jobs, true, &cache, cache_mtime, database);
+    write_cache(database, &cache.lock().unwrap())?;
+    return match failure? {
+        Some(exit_code) => Ok(exit_status(exit_code)),
+        None => Ok(ExitCode::SUCCESS),
+    };
 }
@@ -0,0 +1,290 @@
+use crate::change_detection::Outcome;
+use nix::sys::signal::{kill, killpg, Signal};
+use nix::unistd::Pid;
+use std::fs::File;
+use std::io::{BufRead, BufReader, IsTerminal, Read, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+// how often we check if a command has finished.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// the signals that are forwarded to commands.
+const FORWARDED_SIGNALS: [i32; 3] = [
+    signal_hook::consts::SIGINT,
+    signal_hook::consts::SIGTERM,
+    signal_hook::consts::SIGHUP,
+];
+
+// the number of forwarded signals that have been received, and the last one.
+static SIGNALS_RECEIVED: AtomicUsize = AtomicUsize::new(0);
+static LAST_SIGNAL: AtomicI32 = AtomicI32::new(0);
+// the number of commands that are running. when none are, signals have their default effect.
+static RUNNING: Mutex<usize> = Mutex::new(0);
+static IDLE: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+// commands run in their own process group, so they don't receive the signals sent to run-if
+// (i.e. Ctrl-C in a terminal) directly. while a command is running, these signals are caught
+// and forwarded to its process group instead, so run-if can record that the command was
+// interrupted. when no command is running, they have their default effect.
+// when stdin is a terminal, commands stay in our process group instead (see own_process_group).
+pub fn handle_signals() -> std::io::Result<()> {
+    let idle = IDLE.get_or_init(|| Arc::new(AtomicBool::new(true)));
+    for signal in FORWARDED_SIGNALS {
+        let action = move || {
+            LAST_SIGNAL.store(signal, Ordering::SeqCst);
+            SIGNALS_RECEIVED.fetch_add(1, Ordering::SeqCst);
+        };
+        // safety: the action only uses atomics, which is safe to do in a signal handler.
+        unsafe { signal_hook::low_level::register(signal, action)? };
+        signal_hook::flag::register_conditional_default(signal, idle.clone())?;
+    }
+    return Ok(());
+}
+
+pub fn signals_received() -> usize {
+    return SIGNALS_RECEIVED.load(Ordering::SeqCst);
+}
+
+// the signal that interrupted a command, if any did.
+pub fn interrupted_by() -> Option<i32> {
+    if signals_received() == 0 {
+        return None;
+    }
+    return Some(LAST_SIGNAL.load(Ordering::SeqCst));
+}
+
+// whether commands get their own process group. a process that is not in the foreground
+// process group of the terminal is stopped when it reads from it, so when stdin is a terminal
+// commands stay in our process group. the terminal then sends Ctrl-C to them directly, and
+// only the processes it starts in their own groups are not killed on a timeout.
+pub fn own_process_group() -> bool {
+    return !std::io::stdin().is_terminal();
+}
+
+// send the last signal we received on to a command. `pid` is the process group of the command
+// if it has its own, otherwise the command itself.
+pub fn forward_signal(pid: Pid, own_group: bool) {
+    let Ok(signal) = Signal::try_from(LAST_SIGNAL.load(Ordering::SeqCst)) else {
+        return;
+    };
+    if own_group {
+        let _ = killpg(pid, signal);
+    } else if signal == Signal::SIGTERM {
+        // the terminal sends SIGINT and SIGHUP to its whole foreground process group,
+        // so the command has received those already.
+        let _ = kill(pid, signal);
+    }
+}
+
+// forward the signals we receive to a command until `done` is set, for commands that are
+// waited for in a way that can't check for signals (i.e. while tracing them).
+pub fn forward_signals_until(pid: Pid, own_group: bool, done: &AtomicBool) {
+    let mut signals_forwarded = signals_received();
+    while !done.load(Ordering::SeqCst) {
+        let received = signals_received();
+        if received != signals_forwarded {
+            signals_forwarded = received;
+            forward_signal(pid, own_group);
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+// marks a command as running for as long as it is alive.
+pub struct Running;
+
+impl Running {
+    pub fn start() -> Running {
+        let mut running = RUNNING.lock().unwrap();
+        *running += 1;
+        if let Some(idle) = IDLE.get() {
+            idle.store(false, Ordering::SeqCst);
+        }
+        return Running;
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        let mut running = RUNNING.lock().unwrap();
+        *running -= 1;
+        if *running == 0 {
+            if let Some(idle) = IDLE.get() {
+                idle.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+}
+
+// the most output that is kept from each of stdout and stderr when it is captured.
+pub const MAX_CAPTURED_OUTPUT: usize = 1024 * 1024;
+
+// the output of a command. only the first MAX_CAPTURED_OUTPUT bytes of each stream are kept.
+#[derive(Debug, Default)]
+pub struct Output {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub truncated: bool,
+}
+
+// run a command in its own process group until it exits, the timeout expires, or it is
+// interrupted. signals received while it runs are forwarded to its process group. when the
+// timeout expires, the whole process group is killed, so that any processes it started are
+// killed too. when stdin is a terminal, the command stays in our process group, and only the
+// command itself is killed.
+// if `prefix` is given, every line the command writes to stdout and stderr is printed with
+// the prefix, so that the output of commands that run at the same time can be told apart.
+// if `capture` is true, the output is returned too (it is still printed).
+// if `log` is given, the output is written to it too (without the prefix).
+pub fn run(
+    process: &mut Command,
+    prefix: Option<&str>,
+    timeout: Option<Duration>,
+    capture: bool,
+    log: Option<&File>,
+) -> std::io::Result<(Outcome, Option<Output>)> {
+    let own_group = own_process_group();
+    if own_group {
+        process.process_group(0);
+    }
+    if prefix.is_some() || capture || log.is_some() {
+        process.stdout(Stdio::piped()).stderr(Stdio::piped());
+    }
+    let _running = Running::start();
+    let signals_before = signals_received();
+    let mut child = process.spawn()?;
+    let pid = Pid::from_raw(child.id() as i32);
+    let prefix = prefix.map(|p| p.to_string());
+    let log_for = || -> std::io::Result<Option<File>> {
+        return log.map(|l| l.try_clone()).transpose();
+    };
+    let log_failed = Arc::new(AtomicBool::new(false));
+    let stdout = match child.stdout.take() {
+        Some(r) => Some(forward(
+            r,
+            prefix.clone(),
+            false,
+            capture,
+            log_for()?,
+            log_failed.clone(),
+        )),
+        None => None,
+    };
+    let stderr = match child.stderr.take() {
+        Some(r) => Some(forward(
+            r,
+            prefix.clone(),
+            true,
+            capture,
+            log_for()?,
+            log_failed.clone(),
+        )),
+        None => None,
+    };
+
+    let started = Instant::now();
+    let mut signals_forwarded = signals_before;
+    let outcome = loop {
+        if let Some(status) = child.try_wait()? {
+            if signals_received() != signals_before {
+                break Outcome::Interrupted;
+            }
+            break outcome_of(status);
+        }
+        let received = signals_received();
+        if received != signals_forwarded {
+            signals_forwarded = received;
+            forward_signal(pid, own_group);
+        }
+        if timeout.is_some_and(|t| started.elapsed() >= t) {
+            if own_group {
+                let _ = killpg(pid, Signal::SIGKILL);
+            } else {
+                let _ = child.kill();
+            }
+            child.wait()?;
+            break Outcome::TimedOut;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    };
+    let mut output = Output::default();
+    if let Some(handle) = stdout {
+        (output.stdout, output.truncated) = handle.join().unwrap_or_default();
+    }
+    if let Some(handle) = stderr {
+        let (stderr, truncated) = handle.join().unwrap_or_default();
+        output.stderr = stderr;
+        output.truncated |= truncated;
+    }
+    return Ok((outcome, capture.then_some(output)));
+}
+
+fn outcome_of(status: ExitStatus) -> Outcome {
+    match status.code() {
+        Some(code) => return Outcome::Exited(code),
+        None => return Outcome::Signaled,
+    }
+}
+
+// copy the output of a command to our stdout or stderr, adding the prefix to every line if there
+// is one. if `capture` is true, the output is returned too, along with whether it was truncated.
+// if there is a log, the output is written to it as well. if writing to it fails, a warning is
+// printed (once for both streams, using `log_failed`) and the log is not written to anymore.
+fn forward<R: Read + Send + 'static>(
+    reader: R,
+    prefix: Option<String>,
+    stderr: bool,
+    capture: bool,
+    mut log: Option<File>,
+    log_failed: Arc<AtomicBool>,
+) -> std::thread::JoinHandle<(Vec<u8>, bool)> {
+    return std::thread::spawn(move || {
+        let mut reader = BufReader::new(reader);
+        let mut captured: Vec<u8> = Vec::new();
+        let mut truncated = false;
+        loop {
+            let mut data: Vec<u8> = Vec::new();
+            let read = match prefix {
+                // a prefix can only be added to whole lines.
+                Some(_) => reader.read_until(b'\n', &mut data),
+                None => {
+                    let mut buf = [0u8; 8192];
+                    reader.read(&mut buf).inspect(|n| data.extend(&buf[..*n]))
+                }
+            };
+            if !matches!(read, Ok(n) if n > 0) {
+                break;
+            }
+            if capture {
+                let room = MAX_CAPTURED_OUTPUT - captured.len();
+                truncated |= data.len() > room;
+                captured.extend(&data[..data.len().min(room)]);
+            }
+            if let Some(file) = log.as_mut() {
+                if let Err(e) = file.write_all(&data) {
+                    if !log_failed.swap(true, Ordering::SeqCst) {
+                        eprintln!("Warning: could not write to the log file: {}", e);
+                    }
+                    log = None;
+                }
+            }
+            if let Some(prefix) = &prefix {
+                if !data.ends_with(b"\n") {
+                    data.push(b'\n');
+                }
+                data.splice(0..0, prefix.bytes());
+            }
+            // write everything at once so lines from different commands are not mixed.
+            let _ = if stderr {
+                std::io::stderr().lock().write_all(&data)
+            } else {
+                std::io::stdout().lock().write_all(&data)
+            };
+        }
+        return (captured, truncated);
+    });
+}
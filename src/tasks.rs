@@ -0,0 +1,178 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+// a task file declares named tasks that run-if can run in order. it looks like
+//
+// [tasks.codegen]
+// command = "./scripts/codegen.sh > src/generated.rs"
+// deps = ["schema/"]
+// targets = ["src/generated.rs"]
+//
+// [tasks.build]
+// command = ["cargo", "build"]
+// deps = ["src/"]
+// needs = ["codegen"]
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TaskFile {
+    #[serde(default)]
+    pub tasks: BTreeMap<String, Task>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Task {
+    pub command: TaskCommand,
+    #[serde(default)]
+    pub deps: Vec<PathBuf>,
+    #[serde(default)]
+    pub targets: Vec<PathBuf>,
+    #[serde(default)]
+    pub sentinels: Vec<PathBuf>,
+    #[serde(default)]
+    pub git_deps: Vec<PathBuf>,
+    #[serde(default)]
+    pub env_deps: Vec<String>,
+    #[serde(default)]
+    pub cmd_deps: Vec<String>,
+    #[serde(default)]
+    pub depfile: Option<PathBuf>,
+    // the tasks that have to run before this one.
+    #[serde(default)]
+    pub needs: Vec<String>,
+}
+
+// a command can be given as a string, which is ran by the shell,
+// or as a list of arguments, which is ran directly.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum TaskCommand {
+    Shell(String),
+    Args(Vec<String>),
+}
+
+impl TaskCommand {
+    pub fn args(&self) -> Vec<String> {
+        match self {
+            TaskCommand::Shell(command) => {
+                return vec!["sh".to_string(), "-c".to_string(), command.clone()]
+            }
+            TaskCommand::Args(args) => return args.clone(),
+        }
+    }
+}
+
+pub fn load(path: &Path) -> Result<TaskFile> {
+    let data = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read task file '{}'", path.display()))?;
+    let task_file: TaskFile = toml::from_str(&data)
+        .with_context(|| format!("Could not parse task file '{}'", path.display()))?;
+    for (name, task) in task_file.tasks.iter() {
+        if task.command.args().is_empty() {
+            return Err(anyhow::anyhow!("Task '{}' has an empty command.", name));
+        }
+    }
+    return Ok(task_file);
+}
+
+impl TaskFile {
+    // get the tasks to run, along with all of the tasks they need, ordered so that every
+    // task comes after the tasks it needs. if no tasks are requested, all tasks are ran.
+    pub fn order(&self, requested: &[String]) -> Result<Vec<String>> {
+        let requested: Vec<String> = if requested.is_empty() {
+            self.tasks.keys().cloned().collect()
+        } else {
+            requested.to_vec()
+        };
+        let mut order: Vec<String> = Vec::new();
+        let mut visiting: Vec<String> = Vec::new();
+        let mut done: HashSet<String> = HashSet::new();
+        for name in requested.iter() {
+            self.visit(name, &mut visiting, &mut done, &mut order)?;
+        }
+        return Ok(order);
+    }
+
+    // depth first search. `visiting` holds the chain of tasks that led to this one,
+    // so finding a task that is already in it means there is a cycle.
+    fn visit(
+        &self,
+        name: &String,
+        visiting: &mut Vec<String>,
+        done: &mut HashSet<String>,
+        order: &mut Vec<String>,
+    ) -> Result<()> {
+        if done.contains(name) {
+            return Ok(());
+        }
+        if visiting.contains(name) {
+            visiting.push(name.clone());
+            return Err(anyhow::anyhow!(
+                "Tasks need each other in a cycle: {}",
+                visiting.join(" -> ")
+            ));
+        }
+        let Some(task) = self.tasks.get(name) else {
+            return match visiting.last() {
+                Some(parent) => Err(anyhow::anyhow!(
+                    "Task '{}' needs unknown task '{}'.",
+                    parent,
+                    name
+                )),
+                None => Err(anyhow::anyhow!("Unknown task '{}'.", name)),
+            };
+        };
+        visiting.push(name.clone());
+        for need in task.needs.iter() {
+            self.visit(need, visiting, done, order)?;
+        }
+        visiting.pop();
+        done.insert(name.clone());
+        order.push(name.clone());
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_task_order() {
+        let task_file: TaskFile = toml::from_str(
+            r#"
+            [tasks.test]
+            command = "cargo test"
+            needs = ["build"]
+
+            [tasks.build]
+            command = ["cargo", "build"]
+            needs = ["codegen"]
+
+            [tasks.codegen]
+            command = "./codegen.sh"
+
+            [tasks.cycle-a]
+            command = "true"
+            needs = ["cycle-b"]
+
+            [tasks.cycle-b]
+            command = "true"
+            needs = ["cycle-a"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            task_file.order(&["test".to_string()]).unwrap(),
+            vec!["codegen", "build", "test"]
+        );
+        assert_eq!(
+            task_file.order(&["build".to_string()]).unwrap(),
+            vec!["codegen", "build"]
+        );
+        assert!(task_file.order(&["cycle-a".to_string()]).is_err());
+        assert!(task_file.order(&["missing".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,332 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeSet;
+use std::path::{Component, Path, PathBuf};
+
+// the files a command touched while it ran.
+#[derive(Debug, Default)]
+pub struct TraceResult {
+    pub exit_code: Option<i32>,
+    // whether one of the processes received a signal that interrupts commands (i.e. Ctrl-C).
+    pub interrupted: bool,
+    pub reads: BTreeSet<PathBuf>,
+    pub writes: BTreeSet<PathBuf>,
+}
+
+impl TraceResult {
+    // get the files under `root` that the command read (dependencies) and wrote (targets),
+    // relative to `root`. files that no longer exist are dropped (i.e. temporary files),
+    // and files that the command wrote are not considered dependencies, even if it also read them.
+    pub fn project_files(&self, root: &Path, exclude: &[PathBuf]) -> (Vec<PathBuf>, Vec<PathBuf>) {
+        let in_project = |p: &PathBuf| -> Option<PathBuf> {
+            let relative = p.strip_prefix(root).ok()?.to_path_buf();
+            if exclude.contains(&relative) || exclude.contains(p) || !p.is_file() {
+                return None;
+            }
+            return Some(relative);
+        };
+        let targets: Vec<PathBuf> = self.writes.iter().filter_map(in_project).collect();
+        let dependencies: Vec<PathBuf> = self
+            .reads
+            .iter()
+            .filter(|p| !self.writes.contains(*p))
+            .filter_map(in_project)
+            .collect();
+        return (dependencies, targets);
+    }
+}
+
+// get the files that are not covered by any of the declared paths. a file is covered by a
+// declared path if it is the same file or if it is inside of it (for directories).
+// `files` are relative to `root`, declared paths can be relative or absolute.
+pub fn undeclared(files: &[PathBuf], declared: &[PathBuf], root: &Path) -> Vec<PathBuf> {
+    let declared: Vec<PathBuf> = declared
+        .iter()
+        .map(|d| {
+            let d = normalize(d);
+            return d.strip_prefix(root).map(Path::to_path_buf).unwrap_or(d);
+        })
+        .collect();
+    return files
+        .iter()
+        .filter(|f| !declared.iter().any(|d| f.starts_with(d)))
+        .cloned()
+        .collect();
+}
+
+// normalize a path without touching the filesystem (symlinks are not resolved).
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            c => normalized.push(c),
+        }
+    }
+    return normalized;
+}
+
+// run a command and trace the system calls it (and all of its child processes) make
+// to find the files it opens, stats, and executes. uses ptrace, like strace does.
+// returns after the command and all of its child processes have exited. like process::run,
+// the command gets its own process group unless stdin is a terminal, and the signals we
+// receive are forwarded to it.
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+pub fn run_traced(process: &mut std::process::Command) -> Result<TraceResult> {
+    use nix::sys::ptrace;
+    use nix::sys::signal::Signal;
+    use nix::sys::wait::{waitpid, WaitStatus};
+    use nix::unistd::Pid;
+    use std::os::unix::process::CommandExt;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    // the child asks to be traced and will stop when it calls exec.
+    unsafe {
+        process.pre_exec(|| ptrace::traceme().map_err(std::io::Error::from));
+    }
+    let own_group = crate::process::own_process_group();
+    if own_group {
+        process.process_group(0);
+    }
+    let child = process
+        .spawn()
+        .with_context(|| format!("Error executing the command {:?}", process))?;
+    let root = Pid::from_raw(child.id() as i32);
+
+    match waitpid(root, None).context("Could not wait for traced command")? {
+        WaitStatus::Stopped(_, Signal::SIGTRAP) => {}
+        status => {
+            return Err(anyhow::anyhow!(
+                "Unexpected status while starting traced command: {:?}",
+                status
+            ))
+        }
+    }
+    ptrace::setoptions(
+        root,
+        ptrace::Options::PTRACE_O_TRACESYSGOOD
+            | ptrace::Options::PTRACE_O_TRACEFORK
+            | ptrace::Options::PTRACE_O_TRACEVFORK
+            | ptrace::Options::PTRACE_O_TRACECLONE
+            | ptrace::Options::PTRACE_O_TRACEEXEC
+            | ptrace::Options::PTRACE_O_EXITKILL,
+    )
+    .context("Could not set ptrace options")?;
+    ptrace::syscall(root, None).context("Could not resume traced command")?;
+
+    let done = AtomicBool::new(false);
+    return std::thread::scope(|scope| {
+        scope.spawn(|| crate::process::forward_signals_until(root, own_group, &done));
+        let result = trace(root);
+        done.store(true, Ordering::SeqCst);
+        return result;
+    });
+}
+
+// follow a traced command and all of its child processes until they have exited.
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+fn trace(root: nix::unistd::Pid) -> Result<TraceResult> {
+    use nix::errno::Errno;
+    use nix::libc;
+    use nix::sys::ptrace;
+    use nix::sys::signal::Signal;
+    use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+    use nix::unistd::Pid;
+    use std::collections::{HashMap, HashSet};
+
+    let mut result = TraceResult::default();
+    // the processes we have seen. new processes start with a SIGSTOP that should not be delivered.
+    let mut known: HashSet<Pid> = HashSet::from([root]);
+    // the file each process is in the middle of opening, and whether it is opening it for writing.
+    let mut pending: HashMap<Pid, (PathBuf, bool)> = HashMap::new();
+
+    loop {
+        let status = match waitpid(None, Some(WaitPidFlag::__WALL)) {
+            Ok(status) => status,
+            Err(Errno::ECHILD) => break,
+            Err(Errno::EINTR) => continue,
+            Err(e) => return Err(e).context("Could not wait for traced command"),
+        };
+        match status {
+            WaitStatus::Exited(pid, code) => {
+                if pid == root {
+                    result.exit_code = Some(code);
+                }
+                pending.remove(&pid);
+            }
+            WaitStatus::Signaled(pid, _, _) => {
+                pending.remove(&pid);
+            }
+            WaitStatus::PtraceSyscall(pid) => {
+                if let Ok(info) = ptrace::syscall_info(pid) {
+                    if info.op == libc::PTRACE_SYSCALL_INFO_ENTRY {
+                        let entry = unsafe { info.u.entry };
+                        if let Some((path, write)) = syscall_path(pid, entry.nr, &entry.args) {
+                            if entry.nr as i64 == libc::SYS_execve {
+                                // a successful exec never returns to the old program,
+                                // so record it now.
+                                result.reads.insert(path);
+                            } else {
+                                pending.insert(pid, (path, write));
+                            }
+                        }
+                    } else if info.op == libc::PTRACE_SYSCALL_INFO_EXIT {
+                        let exit = unsafe { info.u.exit };
+                        if let Some((path, write)) = pending.remove(&pid) {
+                            if exit.is_error == 0 {
+                                if write {
+                                    result.writes.insert(path);
+                                } else {
+                                    result.reads.insert(path);
+                                }
+                            }
+                        }
+                    }
+                }
+                let _ = ptrace::syscall(pid, None);
+            }
+            WaitStatus::PtraceEvent(pid, _, _) => {
+                let _ = ptrace::syscall(pid, None);
+            }
+            WaitStatus::Stopped(pid, signal) => {
+                if known.insert(pid) && signal == Signal::SIGSTOP {
+                    let _ = ptrace::syscall(pid, None);
+                } else {
+                    if matches!(signal, Signal::SIGINT | Signal::SIGTERM | Signal::SIGHUP) {
+                        result.interrupted = true;
+                    }
+                    let _ = ptrace::syscall(pid, signal);
+                }
+            }
+            _ => {}
+        }
+    }
+    return Ok(result);
+}
+
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+pub fn run_traced(_process: &mut std::process::Command) -> Result<TraceResult> {
+    return Err(anyhow::anyhow!(
+        "Tracing commands is only supported on Linux."
+    ));
+}
+
+// get the path a system call refers to and whether it opens the file for writing.
+// returns None for system calls we are not interested in.
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+fn syscall_path(pid: nix::unistd::Pid, nr: u64, args: &[u64; 6]) -> Option<(PathBuf, bool)> {
+    use nix::libc;
+
+    let is_write = |flags: u64| -> bool {
+        let flags = flags as i32;
+        flags & libc::O_ACCMODE != libc::O_RDONLY || flags & (libc::O_CREAT | libc::O_TRUNC) != 0
+    };
+    // file descriptors are ints, so only the lower half of the register is meaningful.
+    let fd = |arg: u64| -> i64 { arg as i32 as i64 };
+    let nr = nr as i64;
+    let at_fdcwd = libc::AT_FDCWD as i64;
+    let (dirfd, path_addr, write) = match nr {
+        libc::SYS_openat => (fd(args[0]), args[1], is_write(args[2])),
+        libc::SYS_openat2 => {
+            // the flags are the first field of the `open_how` struct.
+            let how = read_memory(pid, args[2], 8)?;
+            let flags = u64::from_ne_bytes(how.try_into().ok()?);
+            (fd(args[0]), args[1], is_write(flags))
+        }
+        libc::SYS_execve => (at_fdcwd, args[0], false),
+        libc::SYS_newfstatat | libc::SYS_statx => (fd(args[0]), args[1], false),
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_open => (at_fdcwd, args[0], is_write(args[1])),
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_creat => (at_fdcwd, args[0], true),
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_stat | libc::SYS_lstat => (at_fdcwd, args[0], false),
+        _ => return None,
+    };
+    let path = PathBuf::from(read_string(pid, path_addr)?);
+    if path.as_os_str().is_empty() {
+        // i.e. fstatat(fd, "", ..., AT_EMPTY_PATH)
+        return None;
+    }
+    if path.is_absolute() {
+        return Some((normalize(&path), write));
+    }
+    // relative paths are relative to the directory the file descriptor refers to,
+    // or the current directory of the process.
+    let base = if dirfd == at_fdcwd {
+        std::fs::read_link(format!("/proc/{}/cwd", pid)).ok()?
+    } else {
+        std::fs::read_link(format!("/proc/{}/fd/{}", pid, dirfd)).ok()?
+    };
+    return Some((normalize(&base.join(path)), write));
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+fn read_memory(pid: nix::unistd::Pid, addr: u64, len: usize) -> Option<Vec<u8>> {
+    use std::os::unix::fs::FileExt;
+
+    let mem = std::fs::File::open(format!("/proc/{}/mem", pid)).ok()?;
+    let mut buf = vec![0u8; len];
+    mem.read_exact_at(&mut buf, addr).ok()?;
+    return Some(buf);
+}
+
+// read a NUL terminated string from the memory of a traced process.
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+fn read_string(pid: nix::unistd::Pid, addr: u64) -> Option<std::ffi::OsString> {
+    use std::os::unix::ffi::OsStringExt;
+    use std::os::unix::fs::FileExt;
+
+    if addr == 0 {
+        return None;
+    }
+    let mem = std::fs::File::open(format!("/proc/{}/mem", pid)).ok()?;
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut chunk = [0u8; 256];
+    loop {
+        let n = mem
+            .read_at(&mut chunk, addr + bytes.len() as u64)
+            .ok()
+            .filter(|n| *n > 0)?;
+        if let Some(end) = chunk[..n].iter().position(|b| *b == 0) {
+            bytes.extend_from_slice(&chunk[..end]);
+            return Some(std::ffi::OsString::from_vec(bytes));
+        }
+        bytes.extend_from_slice(&chunk[..n]);
+        if bytes.len() > 4096 * 4 {
+            return None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(
+            normalize(Path::new("/work/./build/../src/main.c")),
+            PathBuf::from("/work/src/main.c")
+        );
+    }
+
+    #[test]
+    fn test_undeclared() {
+        let files = vec![
+            PathBuf::from("src/main.c"),
+            PathBuf::from("include/a.h"),
+            PathBuf::from("config.toml"),
+        ];
+        let declared = vec![
+            PathBuf::from("./src/main.c"),
+            PathBuf::from("/work/include"),
+        ];
+        assert_eq!(
+            undeclared(&files, &declared, Path::new("/work")),
+            vec![PathBuf::from("config.toml")]
+        );
+    }
+}
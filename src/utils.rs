@@ -1,4 +1,4 @@
-use anyhow:: Result;
+use anyhow::{Context, Result};
 use std::path::PathBuf;
 
 #[derive(Debug, Clone)]
@@ -56,6 +56,52 @@ impl FileSearch {
         return clone.get_all_paths_under(path);
     }
 }
+
+// read a list of paths from a file, or from stdin if the file is '-'.
+// paths are separated by newlines, or by NUL characters if `nul_separated` is true
+// (i.e. the output of `find -print0` or `git ls-files -z`).
+pub fn read_path_list(file: &PathBuf, nul_separated: bool) -> Result<Vec<PathBuf>> {
+    use std::io::Read;
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut data: Vec<u8> = Vec::new();
+    if file.as_os_str() == "-" {
+        std::io::stdin()
+            .read_to_end(&mut data)
+            .context("Could not read from stdin")?;
+    } else {
+        data = std::fs::read(file)
+            .with_context(|| format!("Could not read file '{}'", file.display()))?;
+    }
+    let separator = if nul_separated { b'\0' } else { b'\n' };
+    return Ok(data
+        .split(|b| *b == separator)
+        .filter(|p| !p.is_empty())
+        .map(|p| PathBuf::from(std::ffi::OsStr::from_bytes(p)))
+        .collect());
+}
+
+// find the file that will be executed for a command name, the same way the shell does.
+// names that contain a '/' are paths, everything else is searched for in PATH.
+pub fn find_executable(name: &str) -> Option<PathBuf> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let is_executable = |p: &PathBuf| -> bool {
+        p.is_file()
+            && std::fs::metadata(p)
+                .map(|m| m.permissions().mode() & 0o111 != 0)
+                .unwrap_or(false)
+    };
+    if name.contains('/') {
+        let path = PathBuf::from(name);
+        return if is_executable(&path) { Some(path) } else { None };
+    }
+    let paths = std::env::var_os("PATH")?;
+    return std::env::split_paths(&paths)
+        .map(|dir| dir.join(name))
+        .find(is_executable);
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,184 @@
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use notify::{RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+// how often files are checked when the operating system can't notify us of changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// watches dependencies and targets for changes.
+pub struct Watch {
+    // the watcher stops when it is dropped, so it has to be kept around.
+    watcher: Box<dyn Watcher>,
+    // the directories that are watched, and if their sub-directories are watched too.
+    watched: Vec<(PathBuf, RecursiveMode)>,
+    receiver: Receiver<notify::Result<notify::Event>>,
+    // absolute paths of the dependencies and targets. a change to any
+    // of these, or to a file inside of one of them, is relevant.
+    paths: Vec<PathBuf>,
+    // absolute paths of files that are never relevant (i.e. the database),
+    // along with any temporary files next to them that start with their name.
+    ignored: Vec<PathBuf>,
+    debounce: Duration,
+}
+
+impl Watch {
+    // start watching. paths must be absolute. uses the native file notification api
+    // of the operating system (i.e. inotify) if possible, otherwise polls.
+    pub fn new(
+        dependencies: &[PathBuf],
+        targets: &[PathBuf],
+        ignored: &[PathBuf],
+        debounce: Duration,
+    ) -> Result<Watch> {
+        let paths: Vec<PathBuf> = dependencies.iter().chain(targets.iter()).cloned().collect();
+        let mut watched: Vec<(PathBuf, RecursiveMode)> = Vec::new();
+        for entry in paths.iter().filter_map(|p| watch_entry(p)) {
+            if !watched.contains(&entry) {
+                watched.push(entry);
+            }
+        }
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let native = notify::recommended_watcher(sender.clone())
+            .map_err(anyhow::Error::from)
+            .and_then(|mut w| {
+                for (path, mode) in watched.iter() {
+                    w.watch(path, *mode)?;
+                }
+                return Ok(w);
+            });
+        let watcher: Box<dyn Watcher> = match native {
+            Ok(w) => Box::new(w),
+            Err(e) => {
+                warn!(
+                    "Could not watch files for changes ({}), polling instead.",
+                    e
+                );
+                let mut w = notify::PollWatcher::new(
+                    sender,
+                    notify::Config::default().with_poll_interval(POLL_INTERVAL),
+                )
+                .context("Could not watch files for changes")?;
+                for (path, mode) in watched.iter() {
+                    w.watch(path, *mode)
+                        .with_context(|| format!("Could not watch '{}'", path.display()))?;
+                }
+                Box::new(w)
+            }
+        };
+        return Ok(Watch {
+            watcher,
+            watched,
+            receiver,
+            paths,
+            ignored: ignored.to_vec(),
+            debounce,
+        });
+    }
+
+    // watch more dependencies or targets, i.e. the ones that were discovered when the command ran.
+    // returns true if any of them were not watched yet.
+    pub fn add(&mut self, paths: &[PathBuf]) -> Result<bool> {
+        let mut added = false;
+        for path in paths.iter() {
+            if self.paths.contains(path) {
+                continue;
+            }
+            added = true;
+            self.paths.push(path.clone());
+            let Some(entry) = watch_entry(path) else {
+                continue;
+            };
+            let covered = self
+                .watched
+                .iter()
+                .any(|(dir, mode)| *mode == RecursiveMode::Recursive && entry.0.starts_with(dir));
+            if covered || self.watched.contains(&entry) {
+                continue;
+            }
+            self.watcher
+                .watch(&entry.0, entry.1)
+                .with_context(|| format!("Could not watch '{}'", entry.0.display()))?;
+            self.watched.push(entry);
+        }
+        return Ok(added);
+    }
+
+    fn is_relevant(&self, event: &notify::Event) -> bool {
+        if event.kind.is_access() {
+            return false;
+        }
+        return event
+            .paths
+            .iter()
+            .filter(|p| !self.is_ignored(p))
+            .any(|p| self.paths.iter().any(|w| p.starts_with(w)));
+    }
+
+    fn is_ignored(&self, path: &Path) -> bool {
+        return self.ignored.iter().any(|i| {
+            path.parent() == i.parent()
+                && path
+                    .file_name()
+                    .zip(i.file_name())
+                    .is_some_and(|(p, i)| p.as_encoded_bytes().starts_with(i.as_encoded_bytes()))
+        });
+    }
+
+    // wait until a dependency or target changes. bursts of changes (i.e. a `git checkout`)
+    // are combined by waiting until nothing has changed for the debounce period.
+    pub fn wait(&self) -> Result<()> {
+        loop {
+            let mut relevant = self.check(self.receiver.recv()?);
+            loop {
+                match self.receiver.recv_timeout(self.debounce) {
+                    Ok(event) => relevant |= self.check(event),
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            if relevant {
+                return Ok(());
+            }
+        }
+    }
+
+    fn check(&self, event: notify::Result<notify::Event>) -> bool {
+        match event {
+            Ok(event) => {
+                let relevant = self.is_relevant(&event);
+                debug!("File event (relevant: {}): {:?}", relevant, event);
+                return relevant;
+            }
+            Err(e) => {
+                warn!("Error while watching files: {}", e);
+                return false;
+            }
+        }
+    }
+}
+
+// the directory to watch for changes to a path. directories are watched recursively. for files
+// (and targets that don't exist yet), the directory they are in is watched instead, because
+// editors often save files by replacing them, which would end a watch on the file itself.
+fn watch_entry(path: &Path) -> Option<(PathBuf, RecursiveMode)> {
+    if path.is_dir() {
+        return Some((path.to_path_buf(), RecursiveMode::Recursive));
+    }
+    return path
+        .parent()
+        .filter(|p| p.is_dir())
+        .map(|p| (p.to_path_buf(), RecursiveMode::NonRecursive));
+}
+
+// make a path absolute without requiring it to exist.
+pub fn absolute(path: &Path) -> Result<PathBuf> {
+    if let Ok(path) = std::fs::canonicalize(path) {
+        return Ok(path);
+    }
+    let current_dir = std::fs::canonicalize(".").context("Could not get current directory")?;
+    return Ok(crate::trace::normalize(&current_dir.join(path)));
+}
@@ -4,6 +4,13 @@ use assert_fs::prelude::*;
 use predicates::prelude::*;
 use std::process::Command;
 
+// run-if with `args`, running in `dir`.
+fn run_if(dir: &assert_fs::TempDir, args: &[&str]) -> Result<Command> {
+    let mut cmd = Command::cargo_bin("run-if")?;
+    cmd.current_dir(dir.path()).args(args);
+    Ok(cmd)
+}
+
 #[test]
 fn missing_target_only() -> Result<()> {
     let mut cmd = Command::cargo_bin("run-if")?;
@@ -39,3 +46,1375 @@ fn single_file_dependency() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn metadata_changes_trigger_run_when_tracked() -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp_dir = assert_fs::TempDir::new()?;
+    let file = tmp_dir.child("script.sh");
+    file.write_str("echo hi")?;
+    let mut cmd = run_if(
+        &tmp_dir,
+        &[
+            "--track-metadata=mode,uid",
+            "-d",
+            "script.sh",
+            "echo",
+            "RUNNING",
+        ],
+    )?;
+    let mut untracked = run_if(&tmp_dir, &["-d", "script.sh", "echo", "UNTRACKED"])?;
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING"));
+    untracked
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("UNTRACKED"));
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING").not());
+
+    std::fs::set_permissions(file.path(), std::fs::Permissions::from_mode(0o755))?;
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING"));
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING").not());
+    untracked
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("UNTRACKED").not());
+
+    Ok(())
+}
+
+#[test]
+fn content_change_with_same_mtime_and_size_triggers_run() -> Result<()> {
+    let tmp_dir = assert_fs::TempDir::new()?;
+    let file = tmp_dir.child("dep.txt");
+    file.write_str("one")?;
+    let mtime = std::fs::metadata(file.path())?.modified()?;
+    let mut cmd = run_if(&tmp_dir, &["-d", "dep.txt", "echo", "RUNNING"])?;
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING"));
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING").not());
+
+    // rewrite the file with the same size and put the old mtime back.
+    file.write_str("two")?;
+    std::fs::File::options()
+        .write(true)
+        .open(file.path())?
+        .set_modified(mtime)?;
+    assert_eq!(std::fs::metadata(file.path())?.modified()?, mtime);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING"));
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING").not());
+
+    Ok(())
+}
+
+#[test]
+fn racily_clean_dependency_is_hashed_again() -> Result<()> {
+    use std::time::{Duration, SystemTime};
+
+    let tmp_dir = assert_fs::TempDir::new()?;
+    let file = tmp_dir.child("dep.txt");
+    file.write_str("one")?;
+    let mtime = SystemTime::now() - Duration::from_secs(100);
+    std::fs::File::options()
+        .write(true)
+        .open(file.path())?
+        .set_modified(mtime)?;
+    let mut cmd = run_if(&tmp_dir, &["-d", "dep.txt", "echo", "RUNNING"])?;
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING"));
+
+    // the stat information of the file still matches the database, so the only way to notice
+    // that the cached hash is wrong is to hash the file again. the file itself can't be changed
+    // without changing its ctime, so the cached hash is changed instead.
+    let database = tmp_dir.child(".run-if.json");
+    let corrupt_hash = |database_mtime: SystemTime| -> Result<()> {
+        let mut data: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(database.path())?)?;
+        for status in data["commands"].as_object_mut().unwrap().values_mut() {
+            status["dependencies"]["dep.txt"]["content_hash"] = "blake3:0000".into();
+        }
+        std::fs::write(database.path(), serde_json::to_string(&data)?)?;
+        std::fs::File::options()
+            .write(true)
+            .open(database.path())?
+            .set_modified(database_mtime)?;
+        Ok(())
+    };
+
+    // written well after the file was modified, so the stat information is trusted.
+    corrupt_hash(mtime + Duration::from_secs(10))?;
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING").not());
+
+    // written at the same time the file was modified, so the file may have changed after it
+    // was hashed, and it has to be hashed again.
+    corrupt_hash(mtime)?;
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING"));
+
+    Ok(())
+}
+
+#[test]
+fn mtime_compare_mode_does_not_read_contents() -> Result<()> {
+    let tmp_dir = assert_fs::TempDir::new()?;
+    let file = tmp_dir.child("dep.txt");
+    file.write_str("one")?;
+    let mtime = std::fs::metadata(file.path())?.modified()?;
+    let mut cmd = run_if(
+        &tmp_dir,
+        &[
+            "--compare",
+            "dep.txt=mtime",
+            "-d",
+            "dep.txt",
+            "echo",
+            "RUNNING",
+        ],
+    )?;
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING"));
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING").not());
+
+    // a content change that keeps the mtime is not detected.
+    file.write_str("two")?;
+    let f = std::fs::File::options().write(true).open(file.path())?;
+    f.set_modified(mtime)?;
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING").not());
+
+    // but a new mtime is, even if the contents are the same.
+    f.set_modified(mtime + std::time::Duration::from_secs(1))?;
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING"));
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING").not());
+
+    Ok(())
+}
+
+#[test]
+fn changing_hash_algorithm_invalidates_cache() -> Result<()> {
+    let tmp_dir = assert_fs::TempDir::new()?;
+    tmp_dir.child("dep.txt").write_str("hi")?;
+    let run = |algorithm: &str| -> Result<assert_cmd::assert::Assert> {
+        Ok(run_if(
+            &tmp_dir,
+            &["--hash", algorithm, "-d", "dep.txt", "echo", "RUNNING"],
+        )?
+        .assert()
+        .success())
+    };
+
+    run("blake3")?.stdout(predicate::str::contains("RUNNING"));
+    run("blake3")?.stdout(predicate::str::contains("RUNNING").not());
+    run("sha256")?.stdout(predicate::str::contains("RUNNING"));
+    run("sha256")?.stdout(predicate::str::contains("RUNNING").not());
+    tmp_dir
+        .child(".run-if.json")
+        .assert(predicate::str::contains(
+            "sha256:8f434346648f6b96df89dda901c5176b10a6d83961dd3c1ac88b59b2dc327aa4",
+        ));
+    run("xxh3")?.stdout(predicate::str::contains("RUNNING"));
+    tmp_dir
+        .child(".run-if.json")
+        .assert(predicate::str::contains("xxh3:"));
+
+    Ok(())
+}
+
+#[test]
+fn hashes_without_algorithm_prefix_are_blake3() -> Result<()> {
+    let tmp_dir = assert_fs::TempDir::new()?;
+    tmp_dir.child("dep.txt").write_str("hi")?;
+    tmp_dir.child("deps/one.txt").write_str("one")?;
+    let mut cmd = run_if(
+        &tmp_dir,
+        &["-d", "dep.txt", "-d", "deps", "echo", "RUNNING"],
+    )?;
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING"));
+    // caches written by older versions have no prefix on their hashes.
+    let database = tmp_dir.child(".run-if.json");
+    let contents = std::fs::read_to_string(database.path())?;
+    assert!(contents.contains("blake3:"));
+    database.write_str(&contents.replace("blake3:", ""))?;
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING").not());
+
+    Ok(())
+}
+
+#[test]
+fn filtered_changes_do_not_trigger_run() -> Result<()> {
+    let tmp_dir = assert_fs::TempDir::new()?;
+    let file = tmp_dir.child("gen.h");
+    file.write_str("// generated at 12:00\r\nint x;\r\n")?;
+    let mut cmd = run_if(
+        &tmp_dir,
+        &[
+            "--filter",
+            "crlf",
+            "--ignore-lines",
+            "gen.h=^// generated at",
+            "-d",
+            "gen.h",
+            "echo",
+            "RUNNING",
+        ],
+    )?;
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING"));
+    file.write_str("// generated at 12:05\nint x;\n")?;
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING").not());
+    file.write_str("// generated at 12:10\nint y;\n")?;
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING"));
+
+    Ok(())
+}
+
+#[test]
+fn ignored_lines_can_contain_equals() -> Result<()> {
+    let tmp_dir = assert_fs::TempDir::new()?;
+    let file = tmp_dir.child("gen.h");
+    file.write_str("version = 1\nint x;\n")?;
+    let run = |ignore: &str| -> Result<assert_cmd::assert::Assert> {
+        Ok(run_if(
+            &tmp_dir,
+            &["--ignore-lines", ignore, "-d", "gen.h", "echo", "RUNNING"],
+        )?
+        .assert()
+        .success())
+    };
+
+    run("gen.h=^version = ")?.stdout(predicate::str::contains("RUNNING"));
+    file.write_str("version = 2\nint x;\n")?;
+    run("gen.h=^version = ")?.stdout(predicate::str::contains("RUNNING").not());
+    // without a dependency in front, the whole argument is the regular expression.
+    file.write_str("version = 3\nint x;\n")?;
+    run("^version = ")?.stdout(predicate::str::contains("RUNNING").not());
+    run("^version = 3")?.stdout(predicate::str::contains("RUNNING"));
+
+    Ok(())
+}
+
+#[test]
+fn changing_filters_invalidates_cache() -> Result<()> {
+    let tmp_dir = assert_fs::TempDir::new()?;
+    tmp_dir
+        .child("gen.h")
+        .write_str("// generated at 12:00\nint x;\n")?;
+    let run = |extra: &[&str]| -> Result<assert_cmd::assert::Assert> {
+        Ok(run_if(&tmp_dir, extra)?
+            .args(["-d", "gen.h", "echo", "RUNNING"])
+            .assert())
+    };
+
+    run(&[])?.stdout(predicate::str::contains("RUNNING"));
+    run(&[])?.stdout(predicate::str::contains("RUNNING").not());
+    run(&["--ignore-lines", "^// generated"])?.stdout(predicate::str::contains("RUNNING"));
+    run(&["--ignore-lines", "^// generated"])?.stdout(predicate::str::contains("RUNNING").not());
+    run(&["--ignore-lines", "gen.h=^int"])?.stdout(predicate::str::contains("RUNNING"));
+
+    Ok(())
+}
+
+#[test]
+fn environment_variable_dependencies() -> Result<()> {
+    let tmp_dir = assert_fs::TempDir::new()?;
+    let run = |cflags: &str, extra: Option<&str>| -> Result<assert_cmd::assert::Assert> {
+        let mut cmd = run_if(&tmp_dir, &["--env-dep", "RUN_IF_TEST_*", "echo", "RUNNING"])?;
+        cmd.env("RUN_IF_TEST_CFLAGS", cflags)
+            .env_remove("RUN_IF_TEST_EXTRA");
+        if let Some(extra) = extra {
+            cmd.env("RUN_IF_TEST_EXTRA", extra);
+        }
+        Ok(cmd.assert().success())
+    };
+
+    run("-O2", None)?.stdout(predicate::str::contains("RUNNING"));
+    run("-O2", None)?.stdout(predicate::str::contains("RUNNING").not());
+    run("-O3", None)?.stdout(predicate::str::contains("RUNNING"));
+    run("-O3", None)?.stdout(predicate::str::contains("RUNNING").not());
+    run("-O3", Some("1"))?.stdout(predicate::str::contains("RUNNING"));
+    run("-O3", None)?.stdout(predicate::str::contains("RUNNING"));
+
+    Ok(())
+}
+
+#[test]
+fn value_dependencies() -> Result<()> {
+    let tmp_dir = assert_fs::TempDir::new()?;
+    let values = tmp_dir.child("values.txt");
+    values.write_str("compiler=gcc 13\nflags=-O2\n")?;
+    let run = |version: &str| -> Result<assert_cmd::assert::Assert> {
+        let version = format!("version={}", version);
+        Ok(run_if(
+            &tmp_dir,
+            &[
+                "--value-dep",
+                &version,
+                "--value-dep-file",
+                "values.txt",
+                "echo",
+                "RUNNING",
+            ],
+        )?
+        .assert()
+        .success())
+    };
+
+    run("v1.0")?.stdout(predicate::str::contains("RUNNING"));
+    run("v1.0")?.stdout(predicate::str::contains("RUNNING").not());
+    run("v1.1")?.stdout(predicate::str::contains("RUNNING"));
+    run("v1.1")?.stdout(predicate::str::contains("RUNNING").not());
+    values.write_str("compiler=gcc 14\nflags=-O2\n")?;
+    run("v1.1")?.stdout(predicate::str::contains("RUNNING"));
+    run("v1.1")?.stdout(predicate::str::contains("RUNNING").not());
+
+    Ok(())
+}
+
+#[test]
+fn command_output_dependencies() -> Result<()> {
+    let tmp_dir = assert_fs::TempDir::new()?;
+    let version = tmp_dir.child("version.txt");
+    version.write_str("1.0")?;
+    let mut cmd = run_if(
+        &tmp_dir,
+        &["--cmd-dep", "cat version.txt", "echo", "RUNNING"],
+    )?;
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING"));
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING").not());
+    version.write_str("1.1")?;
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING"));
+
+    Ok(())
+}
+
+#[test]
+fn executable_is_tracked() -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp_dir = assert_fs::TempDir::new()?;
+    let bin_dir = tmp_dir.child("bin");
+    let script = bin_dir.child("build-step");
+    script.write_str("#!/bin/sh\necho RUNNING v1\n")?;
+    std::fs::set_permissions(script.path(), std::fs::Permissions::from_mode(0o755))?;
+    let path = format!(
+        "{}:{}",
+        bin_dir.path().display(),
+        std::env::var("PATH").unwrap_or_default()
+    );
+    let mut cmd = run_if(&tmp_dir, &["--track-executable", "build-step"])?;
+    cmd.env("PATH", path);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING v1"));
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING").not());
+    script.write_str("#!/bin/sh\necho RUNNING v2\n")?;
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING v2"));
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING").not());
+
+    Ok(())
+}
+
+#[test]
+fn depfile_dependencies_are_tracked() -> Result<()> {
+    let tmp_dir = assert_fs::TempDir::new()?;
+    tmp_dir.child("main.c").write_str("#include \"a.h\"")?;
+    let header = tmp_dir.child("a.h");
+    header.write_str("int a;")?;
+    let mut cmd = run_if(
+        &tmp_dir,
+        &[
+            "-d",
+            "main.c",
+            "--depfile",
+            "main.d",
+            "--",
+            "sh",
+            "-c",
+            "printf 'main.o: main.c \\\\\\n a.h\\n' > main.d; echo RUNNING",
+        ],
+    )?;
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING"));
+    tmp_dir.child("main.d").assert("main.o: main.c \\\n a.h\n");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING").not());
+    header.write_str("int b;")?;
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING"));
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING").not());
+    std::fs::remove_file(header.path())?;
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING"));
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING").not());
+
+    Ok(())
+}
+
+#[test]
+fn unreadable_depfile_drops_discovered_dependencies() -> Result<()> {
+    let tmp_dir = assert_fs::TempDir::new()?;
+    let source = tmp_dir.child("main.c");
+    source.write_str("#include \"a.h\"")?;
+    let header = tmp_dir.child("a.h");
+    header.write_str("int a;")?;
+    let script = tmp_dir.child("build.sh");
+    script.write_str("printf 'main.o: main.c a.h\\n' > main.d; echo RUNNING")?;
+    let mut cmd = run_if(
+        &tmp_dir,
+        &[
+            "-d",
+            "main.c",
+            "--depfile",
+            "main.d",
+            "--",
+            "sh",
+            "build.sh",
+        ],
+    )?;
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING"));
+    // the depfile can't be read, so the command succeeds but a.h is no longer known.
+    script.write_str("rm main.d; mkdir main.d; echo RUNNING")?;
+    source.write_str("int main;")?;
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING"));
+    header.write_str("int b;")?;
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING").not());
+
+    Ok(())
+}
+
+#[test]
+fn dependencies_and_targets_from_files() -> Result<()> {
+    let tmp_dir = assert_fs::TempDir::new()?;
+    tmp_dir.child("one.txt").write_str("1")?;
+    let two = tmp_dir.child("two words.txt");
+    two.write_str("2")?;
+    tmp_dir.child("targets.txt").write_str("out1\n")?;
+    let mut cmd = assert_cmd::Command::from_std(run_if(
+        &tmp_dir,
+        &["-0", "--deps-from", "-", "echo", "RUNNING"],
+    )?);
+    cmd.write_stdin("one.txt\0two words.txt\0");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING"));
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING").not());
+    two.write_str("two")?;
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING"));
+
+    run_if(
+        &tmp_dir,
+        &["--targets-from", "targets.txt", "echo", "RUNNING"],
+    )?
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("RUNNING"));
+
+    Ok(())
+}
+
+#[test]
+fn git_tracked_files_as_dependencies() -> Result<()> {
+    let tmp_dir = assert_fs::TempDir::new()?;
+    let git = |args: &[&str]| -> Result<()> {
+        let status = Command::new("git")
+            .current_dir(tmp_dir.path())
+            .args(args)
+            .status()?;
+        assert!(status.success());
+        Ok(())
+    };
+    git(&["init", "-q"])?;
+    let tracked = tmp_dir.child("src/tracked.txt");
+    tracked.write_str("one")?;
+    tmp_dir.child("src/other.txt").write_str("other")?;
+    git(&["add", "src/tracked.txt", "src/other.txt"])?;
+
+    let mut cmd = run_if(&tmp_dir, &["--git-dep", "src", "echo", "RUNNING"])?;
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING"));
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING").not());
+
+    // untracked files are ignored
+    tmp_dir.child("src/junk.txt").write_str("junk")?;
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING").not());
+
+    // changing a tracked file is a change
+    tracked.write_str("two")?;
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING"));
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING").not());
+
+    // adding a file is a change
+    git(&["add", "src/junk.txt"])?;
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING"));
+
+    // deleting a tracked file is a change
+    std::fs::remove_file(tmp_dir.child("src/other.txt").path())?;
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING"));
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING").not());
+
+    Ok(())
+}
+
+#[test]
+fn traced_dependencies_and_targets() -> Result<()> {
+    let tmp_dir = assert_fs::TempDir::new()?;
+    let input = tmp_dir.child("in.txt");
+    input.write_str("one")?;
+    let output = tmp_dir.child("out.txt");
+    let mut cmd = run_if(
+        &tmp_dir,
+        &[
+            "--trace-deps",
+            "--",
+            "sh",
+            "-c",
+            "cat in.txt > out.txt; echo RUNNING",
+        ],
+    )?;
+
+    // nothing is known about the command until it has run once, so it always runs the first time.
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING"));
+    output.assert("one");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING").not());
+
+    // in.txt was read, so it is a dependency
+    input.write_str("two")?;
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING"));
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING").not());
+
+    // out.txt was written, so it is a target
+    std::fs::remove_file(output.path())?;
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING"));
+    output.assert("two");
+
+    // a command that uses no files in the project still only runs once.
+    let mut cmd = run_if(&tmp_dir, &["--trace-deps", "--", "echo", "NO FILES"])?;
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("NO FILES"));
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("NO FILES").not());
+
+    Ok(())
+}
+
+#[test]
+fn commands_run_after_named_commands() -> Result<()> {
+    let tmp_dir = assert_fs::TempDir::new()?;
+    let input = tmp_dir.child("in.txt");
+    input.write_str("one")?;
+
+    let mut upstream = run_if(
+        &tmp_dir,
+        &[
+            "--name",
+            "generate",
+            "-d",
+            "in.txt",
+            "--",
+            "echo",
+            "GENERATING",
+        ],
+    )?;
+    let mut downstream = run_if(&tmp_dir, &["--after", "generate", "--", "echo", "BUILDING"])?;
+
+    upstream
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("GENERATING"));
+    downstream
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("BUILDING"));
+    upstream
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("GENERATING").not());
+    downstream
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("BUILDING").not());
+
+    input.write_str("two")?;
+    upstream
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("GENERATING"));
+    downstream
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("BUILDING"));
+    downstream
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("BUILDING").not());
+
+    Ok(())
+}
+
+#[test]
+fn changing_a_named_command_runs_commands_after_it() -> Result<()> {
+    let tmp_dir = assert_fs::TempDir::new()?;
+    let mut downstream = run_if(&tmp_dir, &["--after", "up", "--", "echo", "DOWN"])?;
+
+    run_if(&tmp_dir, &["--name", "up", "--", "echo", "v1"])?
+        .assert()
+        .success();
+    downstream
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("DOWN"));
+    // the new command has not run more often than the old one, but it is a different command.
+    run_if(&tmp_dir, &["--name", "up", "--", "echo", "v2"])?
+        .assert()
+        .success();
+    downstream
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("DOWN"));
+    downstream
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("DOWN").not());
+
+    Ok(())
+}
+
+#[test]
+fn tasks_from_task_file() -> Result<()> {
+    let tmp_dir = assert_fs::TempDir::new()?;
+    let schema = tmp_dir.child("schema.txt");
+    schema.write_str("one")?;
+    tmp_dir.child("run-if.toml").write_str(
+        r#"
+[tasks.codegen]
+command = "echo CODEGEN; cat schema.txt > generated.txt"
+deps = ["schema.txt"]
+targets = ["generated.txt"]
+
+[tasks.build]
+command = ["echo", "BUILD"]
+deps = ["generated.txt"]
+needs = ["codegen"]
+"#,
+    )?;
+
+    let mut cmd = run_if(&tmp_dir, &["run", "build"])?;
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::is_match("(?s)CODEGEN.*BUILD")?);
+    cmd.assert().success().stdout(predicate::str::is_empty());
+
+    schema.write_str("two")?;
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::is_match("(?s)CODEGEN.*BUILD")?);
+
+    // only codegen
+    schema.write_str("three")?;
+    run_if(&tmp_dir, &["run", "codegen"])?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("CODEGEN"))
+        .stdout(predicate::str::contains("BUILD").not());
+
+    Ok(())
+}
+
+#[test]
+fn tasks_with_the_same_command() -> Result<()> {
+    let tmp_dir = assert_fs::TempDir::new()?;
+    tmp_dir.child("in.txt").write_str("one")?;
+    tmp_dir.child("run-if.toml").write_str(
+        r#"
+[tasks.one]
+command = "echo SAME"
+deps = ["in.txt"]
+
+[tasks.two]
+command = "echo SAME"
+deps = ["in.txt"]
+
+[tasks.zthree]
+command = "echo THREE"
+needs = ["one", "two"]
+"#,
+    )?;
+
+    let mut cmd = run_if(&tmp_dir, &["run", "zthree"])?;
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::is_match("(?s)SAME.*SAME.*THREE")?);
+    cmd.assert().success().stdout(predicate::str::is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn parallel_tasks() -> Result<()> {
+    let tmp_dir = assert_fs::TempDir::new()?;
+    tmp_dir.child("in.txt").write_str("one")?;
+    // `a` and `b` wait for each other, so they can only finish if they run at the same time.
+    tmp_dir.child("run-if.toml").write_str(
+        r#"
+[tasks.a]
+command = "touch a.started; while [ ! -e b.started ]; do sleep 0.01; done; echo A"
+deps = ["in.txt"]
+
+[tasks.b]
+command = "touch b.started; while [ ! -e a.started ]; do sleep 0.01; done; echo B"
+deps = ["in.txt"]
+
+[tasks.c]
+command = "test -e a.started && test -e b.started && echo C"
+deps = ["in.txt"]
+needs = ["a", "b"]
+"#,
+    )?;
+
+    let mut cmd = assert_cmd::Command::from_std(run_if(&tmp_dir, &["run", "-j", "2"])?);
+    cmd.timeout(std::time::Duration::from_secs(10));
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("[a] A"))
+        .stdout(predicate::str::contains("[b] B"))
+        .stdout(predicate::str::contains("[c] C"));
+
+    Ok(())
+}
+
+#[test]
+fn batch_of_commands() -> Result<()> {
+    let tmp_dir = assert_fs::TempDir::new()?;
+    let a = tmp_dir.child("a.txt");
+    a.write_str("a")?;
+    tmp_dir.child("b.txt").write_str("b")?;
+    let batch = r#"
+# one entry per line
+-d a.txt -- echo "PROCESS A"
+b.txt == echo "PROCESS B"
+{"command": "echo PROCESS C; exit 3", "deps": ["a.txt", "b.txt"]}
+"#;
+
+    let mut cmd = assert_cmd::Command::from_std(run_if(&tmp_dir, &["batch", "-"])?);
+    cmd.write_stdin(batch);
+    // every command runs even though the last one fails.
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::contains("PROCESS A"))
+        .stdout(predicate::str::contains("PROCESS B"))
+        .stdout(predicate::str::contains("PROCESS C"))
+        .stdout(predicate::str::contains("non-zero exit status 3"));
+
+    a.write_str("changed")?;
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::contains("PROCESS A"))
+        .stdout(predicate::str::contains("PROCESS B").not())
+        .stdout(predicate::str::contains("PROCESS C"));
+
+    Ok(())
+}
+
+#[test]
+fn each_changed_file() -> Result<()> {
+    let tmp_dir = assert_fs::TempDir::new()?;
+    tmp_dir.child("docs/a.md").write_str("a")?;
+    tmp_dir.child("docs/b.md").write_str("b")?;
+
+    let mut cmd = run_if(
+        &tmp_dir,
+        &[
+            "--each",
+            "-d",
+            "docs",
+            "-t",
+            "out/{}.html",
+            "--",
+            "sh",
+            "-c",
+            "echo CONVERTING $0; mkdir -p out/docs; cp $0 out/$0.html",
+            "{}",
+        ],
+    )?;
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("CONVERTING docs/a.md"))
+        .stdout(predicate::str::contains("CONVERTING docs/b.md"));
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("CONVERTING").not());
+
+    // only the modified and added files are processed
+    tmp_dir.child("docs/b.md").write_str("changed")?;
+    tmp_dir.child("docs/c.md").write_str("c")?;
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("CONVERTING docs/a.md").not())
+        .stdout(predicate::str::contains("CONVERTING docs/b.md"))
+        .stdout(predicate::str::contains("CONVERTING docs/c.md"));
+
+    // and files with missing targets
+    std::fs::remove_file(tmp_dir.child("out/docs/a.md.html").path())?;
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("CONVERTING docs/a.md"))
+        .stdout(predicate::str::contains("CONVERTING docs/b.md").not());
+
+    // removed files are forgotten, so adding them back is a change.
+    std::fs::remove_file(tmp_dir.child("docs/c.md").path())?;
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("CONVERTING").not());
+    tmp_dir.child("docs/c.md").write_str("c")?;
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("CONVERTING docs/c.md"));
+
+    Ok(())
+}
+
+#[test]
+fn each_runs_dependency_commands_once() -> Result<()> {
+    let tmp_dir = assert_fs::TempDir::new()?;
+    tmp_dir.child("docs/a.md").write_str("a")?;
+    tmp_dir.child("docs/b.md").write_str("b")?;
+
+    run_if(
+        &tmp_dir,
+        &[
+            "--each",
+            "--cmd-dep",
+            "echo probe >> probes.txt; echo 1.0",
+            "-d",
+            "docs",
+            "--",
+            "echo",
+            "CONVERTING",
+            "{}",
+        ],
+    )?
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("CONVERTING docs/a.md"))
+    .stdout(predicate::str::contains("CONVERTING docs/b.md"));
+    tmp_dir.child("probes.txt").assert("probe\n");
+
+    Ok(())
+}
+
+#[test]
+fn changed_dependencies_are_passed_to_command() -> Result<()> {
+    let tmp_dir = assert_fs::TempDir::new()?;
+    let a = tmp_dir.child("a.txt");
+    a.write_str("a")?;
+    tmp_dir.child("b.txt").write_str("b")?;
+
+    let script = "echo \"REASON=$RUN_IF_REASON\"; echo \"CHANGED=$(echo $RUN_IF_CHANGED)\"; echo \"ARGS=$*\"";
+    let run = |extra: &[&str]| -> Result<Command> {
+        let mut cmd = run_if(&tmp_dir, extra)?;
+        cmd.args([
+            "-d",
+            "a.txt",
+            "-d",
+            "b.txt",
+            "--",
+            "sh",
+            "-c",
+            script,
+            "sh",
+            "{changed}",
+        ]);
+        Ok(cmd)
+    };
+    let mut cmd = run(&[])?;
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("REASON=dependency-added"))
+        .stdout(predicate::str::contains("CHANGED=a.txt b.txt"))
+        .stdout(predicate::str::contains("ARGS=a.txt b.txt"));
+
+    a.write_str("changed")?;
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("REASON=dependency-changed"))
+        .stdout(predicate::str::contains("CHANGED=a.txt\n"))
+        .stdout(predicate::str::contains("ARGS=a.txt\n"));
+
+    // nothing changed, so every dependency is passed.
+    run(&["-f"])?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("REASON=forced"))
+        .stdout(predicate::str::contains("ARGS=a.txt b.txt"));
+
+    Ok(())
+}
+
+#[test]
+fn watch_reruns_when_dependencies_change() -> Result<()> {
+    use std::io::BufRead;
+    use std::time::Duration;
+
+    let tmp_dir = assert_fs::TempDir::new()?;
+    let input = tmp_dir.child("src/in.txt");
+    input.write_str("one")?;
+
+    let mut child = run_if(
+        &tmp_dir,
+        &[
+            "watch",
+            "-d",
+            "src",
+            "--",
+            "sh",
+            "-c",
+            "echo RUNNING $(cat src/in.txt)",
+        ],
+    )?
+    .stdout(std::process::Stdio::piped())
+    .spawn()?;
+    let stdout = child.stdout.take().unwrap();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for line in std::io::BufReader::new(stdout).lines() {
+            let _ = sender.send(line.unwrap());
+        }
+    });
+
+    let result = (|| -> Result<()> {
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(10))?,
+            "RUNNING one"
+        );
+        // saving the file without changing it does not run the command.
+        input.write_str("one")?;
+        std::thread::sleep(Duration::from_millis(500));
+        input.write_str("two")?;
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(10))?,
+            "RUNNING two"
+        );
+        Ok(())
+    })();
+    child.kill()?;
+    child.wait()?;
+    result
+}
+
+#[test]
+fn watch_reruns_when_discovered_dependencies_change() -> Result<()> {
+    use std::io::BufRead;
+    use std::time::Duration;
+
+    let tmp_dir = assert_fs::TempDir::new()?;
+    tmp_dir.child("main.c").write_str("#include \"inc/a.h\"")?;
+    let header = tmp_dir.child("inc/a.h");
+    header.write_str("one")?;
+
+    // the header is not under any of the watched paths until the depfile is read.
+    let mut child = run_if(
+        &tmp_dir,
+        &[
+            "watch",
+            "-d",
+            "main.c",
+            "--depfile",
+            "main.d",
+            "--",
+            "sh",
+            "-c",
+            "printf 'main.o: main.c inc/a.h\\n' > main.d; echo RUNNING $(cat inc/a.h)",
+        ],
+    )?
+    .stdout(std::process::Stdio::piped())
+    .spawn()?;
+    let stdout = child.stdout.take().unwrap();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for line in std::io::BufReader::new(stdout).lines() {
+            let _ = sender.send(line.unwrap());
+        }
+    });
+
+    let result = (|| -> Result<()> {
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(10))?,
+            "RUNNING one"
+        );
+        // the header is hashed after the command has run, wait until that is done.
+        std::thread::sleep(Duration::from_millis(500));
+        header.write_str("two")?;
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(10))?,
+            "RUNNING two"
+        );
+        Ok(())
+    })();
+    child.kill()?;
+    child.wait()?;
+    result
+}
+
+#[test]
+fn watch_ignores_database_writes() -> Result<()> {
+    use std::time::Duration;
+
+    let tmp_dir = assert_fs::TempDir::new()?;
+    tmp_dir.child("src/in.txt").write_str("one")?;
+
+    // the database is inside the watched directory. every check runs the
+    // --cmd-dep command, which counts the checks outside of it.
+    let mut child = run_if(
+        &tmp_dir,
+        &[
+            "watch",
+            "--database",
+            "src/.run-if.json",
+            "--cmd-dep",
+            "echo check >> checks.txt",
+            "-d",
+            "src",
+            "--",
+            "true",
+        ],
+    )?
+    .spawn()?;
+    std::thread::sleep(Duration::from_millis(1500));
+    child.kill()?;
+    child.wait()?;
+
+    tmp_dir.child("checks.txt").assert("check\n");
+
+    Ok(())
+}
+
+#[test]
+fn timeout_kills_command() -> Result<()> {
+    let tmp_dir = assert_fs::TempDir::new()?;
+    tmp_dir.child("in.txt").write_str("one")?;
+
+    let started = std::time::Instant::now();
+    let mut cmd = run_if(
+        &tmp_dir,
+        &[
+            "--timeout",
+            "300ms",
+            "-d",
+            "in.txt",
+            "--",
+            "sh",
+            "-c",
+            "echo run >> count; sleep 30 & echo $! > background.pid; wait",
+        ],
+    )?;
+    cmd.assert().failure();
+    assert!(started.elapsed() < std::time::Duration::from_secs(10));
+
+    // the processes started by the command are killed too. they may stay around
+    // as zombies until they are reaped.
+    let pid = std::fs::read_to_string(tmp_dir.child("background.pid").path())?;
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    if let Ok(stat) = std::fs::read_to_string(format!("/proc/{}/stat", pid.trim())) {
+        let state = stat.rsplit_once(')').unwrap().1.trim_start();
+        assert!(
+            state.starts_with('Z'),
+            "background process is still running"
+        );
+    }
+    tmp_dir
+        .child(".run-if.json")
+        .assert(predicate::str::contains("\"outcome\":\"timed-out\""));
+
+    // a run that timed out does not count, so the command runs again.
+    cmd.assert().failure();
+    tmp_dir.child("count").assert("run\nrun\n");
+
+    Ok(())
+}
+
+#[test]
+fn failed_retries_are_a_failure() -> Result<()> {
+    let tmp_dir = assert_fs::TempDir::new()?;
+    tmp_dir.child("in.txt").write_str("one")?;
+    let run = |extra: &[&str]| -> Result<assert_cmd::assert::Assert> {
+        let mut args = extra.to_vec();
+        args.extend([
+            "-d",
+            "in.txt",
+            "--",
+            "sh",
+            "-c",
+            "echo run >> count; exit 1",
+        ]);
+        Ok(run_if(&tmp_dir, &args)?.assert())
+    };
+
+    // a command that failed every retry is handled like a command that failed without retries.
+    run(&["--retries", "1"])?.failure();
+    run(&["--retries", "1"])?.success();
+    tmp_dir.child("count").assert("run\nrun\n");
+    run(&["--retries", "1", "-u"])?.failure();
+    tmp_dir.child("count").assert("run\nrun\nrun\nrun\n");
+
+    std::fs::remove_file(tmp_dir.child("count").path())?;
+    tmp_dir.child("in.txt").write_str("two")?;
+    run(&[])?.failure();
+    run(&[])?.success();
+    tmp_dir.child("count").assert("run\n");
+
+    Ok(())
+}
+
+#[test]
+fn retries_until_success() -> Result<()> {
+    let tmp_dir = assert_fs::TempDir::new()?;
+    tmp_dir.child("in.txt").write_str("one")?;
+
+    // fails twice, then succeeds
+    let flaky = "n=$(cat count 2>/dev/null || echo 0); n=$((n+1)); echo $n > count; echo ATTEMPT $n; [ $n -ge 3 ]";
+    let mut cmd = run_if(
+        &tmp_dir,
+        &[
+            "--retries",
+            "2",
+            "--retry-delay",
+            "10ms",
+            "-d",
+            "in.txt",
+            "--",
+            "sh",
+            "-c",
+            flaky,
+        ],
+    )?;
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("ATTEMPT 3"));
+
+    let database: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(
+        tmp_dir.child(".run-if.json").path(),
+    )?)?;
+    let status = database["commands"]
+        .as_object()
+        .unwrap()
+        .values()
+        .next()
+        .unwrap();
+    let attempts = status["history"][0]["attempts"].as_array().unwrap();
+    assert_eq!(attempts.len(), 3);
+    assert_eq!(attempts[0]["outcome"]["exited"], 1);
+    assert_eq!(attempts[2]["outcome"]["exited"], 0);
+
+    Ok(())
+}
+
+#[test]
+fn signals_are_forwarded_to_command() -> Result<()> {
+    use std::io::BufRead;
+
+    for extra in [&[][..], &["--trace-deps"][..]] {
+        let tmp_dir = assert_fs::TempDir::new()?;
+        tmp_dir.child("in.txt").write_str("one")?;
+
+        let script = "echo run >> count; [ -e done ] && exit 0; \
+                      trap 'echo GOT TERM; exit 1' TERM; \
+                      echo STARTED; while true; do sleep 0.01; done";
+        let run = || -> Result<Command> {
+            let mut cmd = run_if(&tmp_dir, extra)?;
+            cmd.args(["-d", "in.txt", "--", "sh", "-c", script]);
+            Ok(cmd)
+        };
+        let mut child = run()?.stdout(std::process::Stdio::piped()).spawn()?;
+        let mut stdout = std::io::BufReader::new(child.stdout.take().unwrap());
+        let mut line = String::new();
+        stdout.read_line(&mut line)?;
+        assert_eq!(line, "STARTED\n");
+
+        Command::new("kill")
+            .arg("-TERM")
+            .arg(child.id().to_string())
+            .assert()
+            .success();
+        let status = child.wait()?;
+        let rest: Vec<String> = stdout.lines().collect::<std::io::Result<_>>()?;
+        assert_eq!(rest, vec!["GOT TERM", "Command was interrupted"]);
+        assert_eq!(status.code(), Some(128 + 15));
+        // an interruption is recorded, and never counts as a success.
+        tmp_dir
+            .child(".run-if.json")
+            .assert(predicate::str::contains("\"outcome\":\"interrupted\""));
+
+        // the interrupted run did not finish, so it runs again even though nothing changed.
+        tmp_dir.child("done").touch()?;
+        run()?.assert().success();
+        tmp_dir.child("count").assert("run\nrun\n");
+        run()?.assert().success();
+        tmp_dir.child("count").assert("run\nrun\n");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn task_with_missing_executable_fails() -> Result<()> {
+    let tmp_dir = assert_fs::TempDir::new()?;
+    tmp_dir.child("run-if.toml").write_str(
+        r#"
+[tasks.missing]
+command = ["no-such-binary-xyz"]
+targets = ["missing.txt"]
+
+[tasks.other]
+command = "echo OTHER"
+targets = ["other.txt"]
+"#,
+    )?;
+
+    for jobs in ["1", "2"] {
+        let mut cmd = assert_cmd::Command::from_std(run_if(&tmp_dir, &["run", "-j", jobs])?);
+        cmd.timeout(std::time::Duration::from_secs(10));
+        cmd.assert()
+            .failure()
+            .code(1)
+            .stderr(predicate::str::contains("no-such-binary-xyz"));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn finished_tasks_are_recorded_when_a_task_has_an_error() -> Result<()> {
+    let tmp_dir = assert_fs::TempDir::new()?;
+    tmp_dir.child("in.txt").write_str("one")?;
+    tmp_dir.child("run-if.toml").write_str(
+        r#"
+[tasks.good]
+command = "echo GOOD"
+deps = ["in.txt"]
+
+[tasks.missing]
+command = "echo MISSING"
+deps = ["missing.txt"]
+"#,
+    )?;
+
+    let mut cmd = run_if(&tmp_dir, &["run"])?;
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::contains("GOOD"))
+        .stderr(predicate::str::contains("'missing.txt' does not exist"));
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::contains("GOOD").not());
+
+    Ok(())
+}
@@ -0,0 +1,10 @@
+  $ echo "one" > in.txt
+  $ echo "two" > config.txt
+  $ $TESTDIR/../../target/debug/run-if --check-deps -d in.txt -- sh -c "cat in.txt config.txt > out.txt"
+  Undeclared dependency: 'config.txt' was read but is not covered by a dependency.
+  Undeclared target: 'out.txt' was written but is not covered by a target.
+  [1]
+  $ $TESTDIR/../../target/debug/run-if --check-deps -d in.txt -d config.txt -t out.txt -- sh -c "cat in.txt config.txt > out.txt"
+  $ ls .run-if.json
+  ls: cannot access '.run-if.json': No such file or directory
+  [2]
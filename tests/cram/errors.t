@@ -13,8 +13,49 @@
   HI
   $ $TESTDIR/../../target/debug/run-if -d deps echo HI
   $ $TESTDIR/../../target/debug/run-if -d deps ech HI
+  Error: Could not run command 'ech'
   
-  thread 'main' * panicked * (glob)
-  Error * Command parts: ["ech", "HI"]* (glob)
-  note: run * (glob)
-  [101]
+  Caused by:
+      No such file or directory (os error 2)
+  [1]
+  $ echo "int x;" > gen.h
+  $ $TESTDIR/../../target/debug/run-if --compare gen.c=mtime -d gen.h echo HI
+  Error: 'gen.c' in --compare is not a dependency.
+  [1]
+  $ $TESTDIR/../../target/debug/run-if --filter gen.c=crlf -d gen.h echo HI
+  Error: 'gen.c' in --filter is not a dependency.
+  [1]
+  $ $TESTDIR/../../target/debug/run-if --value-dep version echo HI
+  error: invalid value 'version' for '--value-dep <NAME=VALUE>': expected NAME=VALUE, got 'version'
+  
+  For more information, try '--help'.
+  [2]
+  $ $TESTDIR/../../target/debug/run-if --cmd-dep "exit 1" echo HI
+  Error: dependency command 'exit 1' did not complete successfully.
+  [1]
+  $ $TESTDIR/../../target/debug/run-if --deps-from - --targets-from - echo HI
+  Error: stdin ('-') can only be read once by --deps-from and --targets-from.
+  [1]
+  $ $TESTDIR/../../target/debug/run-if --after generate echo HI
+  Error: no command named 'generate' found in database '.run-if.json'. Run it with --name first.
+  [1]
+  $ $TESTDIR/../../target/debug/run-if -f -- {changed}
+  Error: the command is empty after replacing '{changed}', there are no dependencies.
+  [1]
+  $ echo "-d gen.h --no-such-option -- echo HI" | $TESTDIR/../../target/debug/run-if batch -
+  Error: invalid entry on line 1 of '-': error: unexpected argument '--no-such-option' found
+  
+    tip: to pass '--no-such-option' as a value, use '-- --no-such-option'
+  
+  Usage: run-if * (glob)
+  
+  For more information, try '--help'.
+  
+  [1]
+  $ printf '[tasks.build]\ncommand = "echo HI"\n' > run-if.toml
+  $ $TESTDIR/../../target/debug/run-if run deploy
+  Error: Unknown task 'deploy'.
+  [1]
+  $ $TESTDIR/../../target/debug/run-if --each --name convert -d gen.h -- cat {}
+  Error: --name and --after can not be used with --each.
+  [1]
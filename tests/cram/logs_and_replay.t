@@ -0,0 +1,26 @@
+  $ echo "one" > in.txt
+  $ $TESTDIR/../../target/debug/run-if --capture-output -d in.txt -- sh -c "echo WARNING: unused; echo ERROR: deprecated >&2" 2> err.txt
+  WARNING: unused
+  $ cat err.txt
+  ERROR: deprecated
+  $ $TESTDIR/../../target/debug/run-if --capture-output --replay -d in.txt -- sh -c "echo WARNING: unused; echo ERROR: deprecated >&2" 2> err.txt
+  WARNING: unused
+  $ cat err.txt
+  ERROR: deprecated
+  $ $TESTDIR/../../target/debug/run-if --capture-output -d in.txt -- sh -c "echo WARNING: unused; echo ERROR: deprecated >&2"
+  $ $TESTDIR/../../target/debug/run-if --name gen --log-dir logs -d in.txt -- sh -c "echo GENERATED; sleep 0.1; echo NOTE >&2" 2> /dev/null
+  GENERATED
+  $ ls logs | wc -l
+  1
+  $ $TESTDIR/../../target/debug/run-if logs gen
+  # command: sh -c 'echo GENERATED; sleep 0.1; echo NOTE >&2'
+  # started: * (glob)
+  # reasons: dependency-added
+  # changed: in.txt
+  GENERATED
+  NOTE
+  # exit code: 0
+  # duration: * (glob)
+  $ $TESTDIR/../../target/debug/run-if logs missing
+  Error: no command named 'missing' found in database '.run-if.json'.
+  [1]
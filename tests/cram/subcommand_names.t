@@ -0,0 +1,12 @@
+  $ mkdir bin
+  $ printf '#!/bin/sh\necho "$0 $@"\n' > bin/watch
+  $ chmod +x bin/watch
+  $ cp bin/watch bin/logs
+  $ echo "HI" > dep1.txt
+  $ PATH="$PWD/bin:$PATH" $TESTDIR/../../target/debug/run-if -d dep1.txt -- watch hi
+  */bin/watch hi (glob)
+  $ PATH="$PWD/bin:$PATH" $TESTDIR/../../target/debug/run-if -f -- logs hi
+  */bin/logs hi (glob)
+  $ PATH="$PWD/bin:$PATH" $TESTDIR/../../target/debug/run-if logs hi
+  Error: no command named 'hi' found in database '.run-if.json'.
+  [1]
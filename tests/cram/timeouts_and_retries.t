@@ -0,0 +1,19 @@
+  $ echo "one" > in.txt
+  $ $TESTDIR/../../target/debug/run-if --timeout 300ms -d in.txt -- sleep 30
+  Command timed out after 300ms
+  Command did not complete successfully
+  [1]
+  $ cat > flaky.sh <<'SH'
+  > n=$(cat count 2>/dev/null || echo 0)
+  > n=$((n+1))
+  > echo $n > count
+  > echo ATTEMPT $n
+  > [ $n -ge 3 ]
+  > SH
+  $ $TESTDIR/../../target/debug/run-if --retries 2 --retry-delay 10ms -d in.txt -- sh flaky.sh
+  ATTEMPT 1
+  Command did not succeed, trying again (1 of 2).
+  ATTEMPT 2
+  Command did not succeed, trying again (2 of 2).
+  ATTEMPT 3
+  $ $TESTDIR/../../target/debug/run-if --retries 2 --retry-delay 10ms -d in.txt -- sh flaky.sh